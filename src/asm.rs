@@ -0,0 +1,579 @@
+//! Audit inline assembly: the options, operands and clobbers of every `asm!`, and whether the
+//! enclosing function is guarded for the architecture and features it needs.
+use std::{collections::HashMap, ffi::OsString, io::Write};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::Result;
+use itertools::Itertools;
+use rustc_ast::InlineAsmOptions;
+use rustc_hir::{
+    self as hir,
+    def::DefKind,
+    intravisit::{self, Visitor},
+};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{def_id::LocalDefId, source_map::SourceMap, Span, Symbol};
+
+use crate::{
+    report::Files,
+    run::cargo_check,
+    safe::unsafety_visitor::{self, UnsafeOpKind},
+};
+
+pub(crate) fn run(args: crate::opts::AsmArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    tracing::debug!("auditing inline assembly");
+    cargo_check("asm", None, &args.package, Some("-Zthir-unsafeck"), rem)
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("asm")
+    );
+    tracing::trace!("in whynot asm rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut AsmCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+/// The options that limit what the compiler has to assume an `asm!` block does, with what it may
+/// do without them.
+const OPTIONS: [(InlineAsmOptions, &str, &str); 4] = [
+    (
+        InlineAsmOptions::PURE,
+        "pure",
+        "it may have side effects, so it can't be removed or merged with an identical block",
+    ),
+    (
+        InlineAsmOptions::NOMEM,
+        "nomem",
+        "it may read and write any memory reachable by the program",
+    ),
+    (
+        InlineAsmOptions::NOSTACK,
+        "nostack",
+        "it may push to the stack, so the red zone below the stack pointer can't be used",
+    ),
+    (
+        InlineAsmOptions::PRESERVES_FLAGS,
+        "preserves_flags",
+        "it may change the condition flags",
+    ),
+];
+
+/// The options that promise something about the block without being needed to make it cheaper,
+/// with what they promise.
+const GUARANTEES: [(InlineAsmOptions, &str, &str); 2] = [
+    (
+        InlineAsmOptions::READONLY,
+        "readonly",
+        "it may read but not write memory",
+    ),
+    (
+        InlineAsmOptions::NORETURN,
+        "noreturn",
+        "it never returns, falling through the end of it is undefined behavior",
+    ),
+];
+
+/// The options that don't make any guarantees about the block.
+const OTHER_OPTIONS: [(InlineAsmOptions, &str); 3] = [
+    (InlineAsmOptions::ATT_SYNTAX, "att_syntax"),
+    (InlineAsmOptions::RAW, "raw"),
+    (InlineAsmOptions::MAY_UNWIND, "may_unwind"),
+];
+
+/// Collects the `asm!` expressions of a body, without looking into nested bodies.
+struct AsmFinder<'tcx> {
+    found: Vec<(Span, &'tcx hir::InlineAsm<'tcx>)>,
+}
+
+impl<'tcx> Visitor<'tcx> for AsmFinder<'tcx> {
+    fn visit_expr(&mut self, expr: &'tcx hir::Expr<'tcx>) {
+        if let hir::ExprKind::InlineAsm(asm) = expr.kind {
+            self.found.push((expr.span, asm));
+        }
+        intravisit::walk_expr(self, expr);
+    }
+}
+
+/// How an operand is passed, e.g. `inout(reg)`, or `None` for a clobber.
+fn operand(op: &hir::InlineAsmOperand<'_>) -> Option<String> {
+    let late = |late: bool, name: &str| {
+        if late {
+            format!("late{name}")
+        } else {
+            name.to_string()
+        }
+    };
+    Some(match op {
+        hir::InlineAsmOperand::In { reg, .. } => format!("in({reg})"),
+        hir::InlineAsmOperand::Out { expr: None, .. } => return None,
+        hir::InlineAsmOperand::Out { reg, late, .. } => format!("{}({reg})", late(*late, "out")),
+        hir::InlineAsmOperand::InOut { reg, late, .. }
+        | hir::InlineAsmOperand::SplitInOut { reg, late, .. } => {
+            format!("{}({reg})", late(*late, "inout"))
+        }
+        hir::InlineAsmOperand::Const { .. } => "const".to_string(),
+        hir::InlineAsmOperand::SymFn { .. } | hir::InlineAsmOperand::SymStatic { .. } => {
+            "sym".to_string()
+        }
+    })
+}
+
+/// The target feature a register class can't be used without.
+fn required_feature(arch: &str, class: &str) -> Option<&'static str> {
+    match (arch, class) {
+        ("x86" | "x86_64", "xmm_reg") => Some("sse"),
+        ("x86" | "x86_64", "ymm_reg") => Some("avx"),
+        ("x86" | "x86_64", "zmm_reg" | "kreg") => Some("avx512f"),
+        ("aarch64", "vreg" | "vreg_low16") => Some("neon"),
+        ("arm", "sreg" | "sreg_low16" | "dreg" | "dreg_low16" | "dreg_low8") => Some("vfp2"),
+        ("arm", "qreg" | "qreg_low8" | "qreg_low4") => Some("neon"),
+        _ => None,
+    }
+}
+
+/// The outer attributes right before the byte `pos`, innermost first. Comments between them
+/// are skipped, and an attribute may span several lines, like rustfmt splits long `cfg`s.
+fn attributes_before(source: &str, pos: usize) -> Vec<&str> {
+    let mut attributes = vec![];
+    let mut rest = &source[..pos];
+    loop {
+        rest = rest.trim_end();
+        let line_start = rest.rfind('\n').map_or(0, |newline| newline + 1);
+        if rest[line_start..].trim_start().starts_with("//") {
+            rest = &rest[..line_start];
+        } else if rest.ends_with(']')
+            && let Some(open) = matching_open(rest)
+            // Inner attributes before the item belong to the module.
+            && rest[..open].ends_with('#')
+        {
+            attributes.push(&rest[open - 1..]);
+            rest = &rest[..open - 1];
+        } else {
+            return attributes;
+        }
+    }
+}
+
+/// The inner attributes at the start of a module, from the byte `pos` on. Comments between them
+/// are skipped, and an attribute may span several lines.
+fn inner_attributes(source: &str, pos: usize) -> Vec<&str> {
+    let mut attributes = vec![];
+    let mut rest = &source[pos..];
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |newline| &rest[newline..]);
+        } else if rest.starts_with("#![")
+            && let Some(end) = matching_close(rest)
+        {
+            attributes.push(&rest[..end]);
+            rest = &rest[end..];
+        } else {
+            return attributes;
+        }
+    }
+}
+
+/// The position of the `[` matching the `]` `text` ends with.
+fn matching_open(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices().rev() {
+        match c {
+            ']' => depth += 1,
+            '[' if depth == 1 => return Some(i),
+            '[' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The length of `text` up to and including the `]` matching its first `[`.
+fn matching_close(text: &str) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 1 => return Some(i + 1),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// A `cfg` predicate, like `all(unix, target_arch = "x86_64")`.
+#[derive(Debug, PartialEq, Eq)]
+enum Cfg {
+    Option(String, Option<String>),
+    All(Vec<Cfg>),
+    Any(Vec<Cfg>),
+    Not(Box<Cfg>),
+}
+
+impl Cfg {
+    /// The predicate of a `#[cfg(...)]` or `#![cfg(...)]` attribute.
+    fn of_attribute(attribute: &str) -> Option<Cfg> {
+        let mut predicate = attribute
+            .strip_prefix('#')?
+            .trim_start_matches('!')
+            .strip_prefix('[')?
+            .trim_start()
+            .strip_prefix("cfg")?
+            .trim_start()
+            .strip_prefix('(')?;
+        Cfg::parse(&mut predicate)
+    }
+
+    /// Parse the predicate at the start of `input`, leaving the rest in it.
+    fn parse(input: &mut &str) -> Option<Cfg> {
+        let trimmed = input.trim_start();
+        let end = trimmed
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(trimmed.len());
+        let (name, rest) = trimmed.split_at(end);
+        if name.is_empty() {
+            return None;
+        }
+        *input = rest.trim_start();
+        if let Some(rest) = input.strip_prefix('(') {
+            *input = rest;
+            let mut list = vec![];
+            loop {
+                *input = input.trim_start();
+                if let Some(rest) = input.strip_prefix(')') {
+                    *input = rest;
+                    break;
+                }
+                list.push(Cfg::parse(input)?);
+                *input = input.trim_start();
+                if let Some(rest) = input.strip_prefix(',') {
+                    *input = rest;
+                } else if !input.starts_with(')') {
+                    return None;
+                }
+            }
+            match name {
+                "all" => Some(Cfg::All(list)),
+                "any" => Some(Cfg::Any(list)),
+                "not" if list.len() == 1 => Some(Cfg::Not(Box::new(list.pop()?))),
+                _ => None,
+            }
+        } else if let Some(rest) = input.strip_prefix('=') {
+            let value = rest.trim_start().strip_prefix('"')?;
+            let end = value.find('"')?;
+            *input = &value[end + 1..];
+            Some(Cfg::Option(name.to_string(), Some(value[..end].to_string())))
+        } else {
+            Some(Cfg::Option(name.to_string(), None))
+        }
+    }
+
+    /// The architectures code under the predicate can only be built for, `None` if it isn't
+    /// restricted to any. `not` is never taken as a restriction.
+    fn arches(&self) -> Option<Vec<&str>> {
+        match self {
+            Cfg::Option(name, Some(arch)) if name == "target_arch" => Some(vec![arch.as_str()]),
+            Cfg::Option(..) | Cfg::Not(_) => None,
+            Cfg::All(list) => list.iter().filter_map(Cfg::arches).reduce(|arches, other| {
+                arches
+                    .into_iter()
+                    .filter(|arch| other.contains(arch))
+                    .collect()
+            }),
+            Cfg::Any(list) => list
+                .iter()
+                .map(Cfg::arches)
+                .collect::<Option<Vec<_>>>()
+                .map(|arches| arches.concat()),
+        }
+    }
+
+    /// Whether code under the predicate only builds for a set of architectures including `arch`.
+    fn guards(&self, arch: &str) -> bool {
+        self.arches()
+            .map_or(false, |arches| arches.contains(&arch))
+    }
+}
+
+/// Where the code around `owner` is restricted to the target's architecture: a
+/// `cfg(target_arch)` on the fn or one of the items it is in, or an inner `#![cfg(target_arch)]`
+/// in one of its modules. A `cfg!` check in the body is no guard, as the block still has to
+/// build for every architecture.
+///
+/// `cfg`s are gone after expansion, so this looks at the source instead.
+fn arch_guard(tcx: TyCtxt<'_>, owner: LocalDefId) -> Option<String> {
+    let sm = tcx.sess.source_map();
+    let arch = &*tcx.sess.target.arch;
+    let source_at = |span: Span| {
+        let pos = sm.lookup_byte_offset(span.lo());
+        pos.sf.src.clone().map(|source| (source, pos.pos.0 as usize))
+    };
+    let mut current = Some(owner.to_def_id());
+    while let Some(did) = current {
+        current = tcx.opt_parent(did);
+        let mut attributes = vec![];
+        // The crate root has no outer attributes.
+        if current.is_some()
+            && let Some((source, pos)) = source_at(tcx.def_span(did))
+        {
+            attributes.extend(attributes_before(&source, pos).into_iter().map(str::to_string));
+        }
+        if tcx.def_kind(did) == DefKind::Mod
+            && let Some(module) = did.as_local()
+            && let Some((source, pos)) = source_at(tcx.hir().get_module(module).0.spans.inner_span)
+        {
+            attributes.extend(inner_attributes(&source, pos).into_iter().map(str::to_string));
+        }
+        let guard = attributes.into_iter().find(|attribute| {
+            Cfg::of_attribute(attribute).map_or(false, |cfg| cfg.guards(arch))
+        });
+        if let Some(attribute) = guard {
+            let attribute = attribute.split_whitespace().join(" ");
+            return Some(match current {
+                Some(_) => format!("`{attribute}` on `{}`", tcx.def_path_str(did)),
+                None => format!("`{attribute}` on the crate"),
+            });
+        }
+    }
+    None
+}
+
+fn clobbers(sm: &SourceMap, asm: &hir::InlineAsm<'_>) -> Vec<String> {
+    let clobbered = asm
+        .operands
+        .iter()
+        .filter(|(op, _)| operand(op).is_none())
+        .map(|(op, span)| (op.reg().map(|reg| reg.to_string()), *span))
+        .collect_vec();
+    // `clobber_abi` expands to one output per register it clobbers, all with its span.
+    clobbered
+        .iter()
+        .group_by(|(_, span)| *span)
+        .into_iter()
+        .map(|(span, regs)| {
+            let regs = regs.filter_map(|(reg, _)| reg.clone()).collect_vec();
+            match sm.span_to_snippet(span) {
+                Ok(snippet) if snippet.starts_with("clobber_abi") => {
+                    format!("{snippet}: {} registers", regs.len())
+                }
+                _ => regs.join(", "),
+            }
+        })
+        .collect()
+}
+
+pub struct AsmCallback;
+
+impl AsmCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let hir = tcx.hir();
+        let sm = tcx.sess.source_map();
+        let mut blocks = HashMap::new();
+        for did in hir.body_owners() {
+            let mut finder = AsmFinder { found: vec![] };
+            finder.visit_body(hir.body(hir.body_owned_by(did)));
+            blocks.extend(finder.found);
+        }
+
+        let mut sites = vec![];
+        for did in hir.body_owners() {
+            if tcx.is_closure(did.to_def_id()) {
+                continue;
+            }
+            let violations = unsafety_visitor::check_unsafety(
+                tcx,
+                unsafety_visitor::with_opt_const_param(tcx, did),
+            );
+            for (kind, owner, span) in violations {
+                if let (UnsafeOpKind::UseOfInlineAssembly, Some(asm)) = (kind, blocks.get(&span)) {
+                    sites.push((owner, span, *asm));
+                }
+            }
+        }
+
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut files = Files::new(sm);
+        let arch = &tcx.sess.target.arch;
+        let (mut unguarded, mut missing_features) = (0, 0);
+        for (owner, span, asm) in &sites {
+            let owner_path = tcx.def_path_str(owner.to_def_id());
+            let mut labels = vec![];
+            labels.extend(files.label(*span, LabelStyle::Primary, ""));
+            for (op, op_span) in asm.operands {
+                if let Some(operand) = operand(op) {
+                    labels.extend(files.label(*op_span, LabelStyle::Secondary, operand));
+                }
+            }
+
+            let mut notes = vec![];
+            let options = OPTIONS
+                .iter()
+                .chain(&GUARANTEES)
+                .map(|(option, name, _)| (*option, *name))
+                .chain(OTHER_OPTIONS)
+                .filter(|(option, _)| asm.options.contains(*option))
+                .map(|(_, name)| name)
+                .join(", ");
+            notes.push(if options.is_empty() {
+                "no options".to_string()
+            } else {
+                format!("options: {options}")
+            });
+            for (option, name, without) in OPTIONS {
+                if option == InlineAsmOptions::NOMEM
+                    && asm.options.contains(InlineAsmOptions::READONLY)
+                {
+                    notes.push(format!(
+                        "without `{name}`, it may read any memory reachable by the program, but \
+                         `readonly` keeps it from writing to it"
+                    ));
+                } else if !asm.options.contains(option) {
+                    notes.push(format!("without `{name}`, {without}"));
+                }
+            }
+            for (option, name, with) in GUARANTEES {
+                if asm.options.contains(option) {
+                    notes.push(format!("with `{name}`, {with}"));
+                }
+            }
+            let clobbers = clobbers(sm, asm);
+            if !clobbers.is_empty() {
+                notes.push(format!("clobbers: {}", clobbers.join("; ")));
+            }
+
+            match arch_guard(tcx, *owner) {
+                Some(guard) => notes.push(format!("guarded by {guard}")),
+                None => {
+                    unguarded += 1;
+                    notes.push(format!(
+                        "no `cfg(target_arch)` guard found around `{owner_path}`, the block only \
+                         builds for `{arch}`"
+                    ));
+                }
+            }
+            let enabled = &tcx.codegen_fn_attrs(owner.to_def_id()).target_features;
+            if !enabled.is_empty() {
+                notes.push(format!(
+                    "`{owner_path}` enables {} with `#[target_feature]`",
+                    enabled.iter().map(|feature| format!("`{feature}`")).join(", ")
+                ));
+            }
+            let needed = asm
+                .operands
+                .iter()
+                .filter_map(|(op, _)| {
+                    let class = op.reg()?.reg_class().name();
+                    let feature = required_feature(arch, class.as_str())?;
+                    Some((class, Symbol::intern(feature)))
+                })
+                .unique()
+                .filter(|(_, feature)| {
+                    !enabled.contains(feature) && !tcx.sess.target_features.contains(feature)
+                })
+                .collect_vec();
+            if !needed.is_empty() {
+                missing_features += 1;
+            }
+            for (class, feature) in needed {
+                notes.push(format!(
+                    "`{class}` needs `{feature}`, which is neither enabled for the target nor by \
+                     `#[target_feature(enable = \"{feature}\")]` on `{owner_path}`"
+                ));
+            }
+
+            files.emit(
+                &mut io,
+                &Diagnostic::note()
+                    .with_message(format!("inline assembly in `{owner_path}`"))
+                    .with_labels(labels)
+                    .with_notes(notes),
+            )?;
+        }
+        writeln!(
+            io,
+            "{} asm blocks, {unguarded} without a `target_arch` guard, {missing_features} using \
+             registers of a feature that is not enabled",
+            sites.len()
+        )?;
+        Ok(())
+    }
+}
+
+impl rustc_driver::Callbacks for AsmCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    AsmCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
+
+#[test]
+#[cfg(test)]
+fn test_attributes_before() {
+    let source = "mod a;\n\n#[cfg(target_arch = \"x86_64\")]\n/// Docs.\n#[inline]\nfn f() {}\n";
+    assert_eq!(
+        attributes_before(source, source.find("fn f").unwrap()),
+        vec!["#[inline]", "#[cfg(target_arch = \"x86_64\")]"]
+    );
+    assert!(attributes_before(source, 0).is_empty());
+
+    let source = "#![no_std]\n#[cfg(all(\n    target_arch = \"x86_64\",\n\
+                  \x20   target_feature = \"aes\"\n))]\n// Comment.\nfn f() {}\n";
+    assert_eq!(
+        attributes_before(source, source.find("fn f").unwrap()),
+        vec!["#[cfg(all(\n    target_arch = \"x86_64\",\n    target_feature = \"aes\"\n))]"]
+    );
+}
+
+#[test]
+#[cfg(test)]
+fn test_inner_attributes() {
+    let source = "//! Docs.\n\n#![cfg(any(\n    target_arch = \"x86\",\n\
+                  \x20   target_arch = \"x86_64\"\n))]\n#![allow(unused)]\n\nfn f() {}\n";
+    assert_eq!(
+        inner_attributes(source, 0),
+        vec![
+            "#![cfg(any(\n    target_arch = \"x86\",\n    target_arch = \"x86_64\"\n))]",
+            "#![allow(unused)]"
+        ]
+    );
+    assert!(inner_attributes(source, source.find("fn f").unwrap()).is_empty());
+}
+
+#[test]
+#[cfg(test)]
+fn test_cfg_guards() {
+    let guards = |attribute| Cfg::of_attribute(attribute).map_or(false, |cfg| cfg.guards("x86_64"));
+    assert!(guards("#[cfg(target_arch = \"x86_64\")]"));
+    assert!(guards("#![cfg(target_arch = \"x86_64\")]"));
+    assert!(guards("#[cfg(all(unix, target_arch = \"x86_64\"))]"));
+    assert!(guards("#[cfg(any(target_arch = \"x86\", target_arch = \"x86_64\"))]"));
+    assert!(!guards("#[cfg(target_arch = \"aarch64\")]"));
+    assert!(!guards("#[cfg(not(target_arch = \"aarch64\"))]"));
+    assert!(!guards("#[cfg(any(unix, target_arch = \"x86_64\"))]"));
+    assert!(!guards("#[cfg_attr(target_arch = \"x86_64\", inline)]"));
+    assert!(!guards("#[doc = \"target_arch = x86_64\"]"));
+    assert_eq!(
+        Cfg::of_attribute("#[cfg(not(unix))]"),
+        Some(Cfg::Not(Box::new(Cfg::Option("unix".to_string(), None))))
+    );
+}
@@ -0,0 +1,315 @@
+pub mod baseline;
+pub mod changed;
+pub mod inventory;
+pub mod metrics;
+
+use std::{ffi::OsString, io::Write, path::PathBuf};
+
+use eyre::Result;
+use itertools::Itertools;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::def_id::{DefId, LOCAL_CRATE};
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use self::{
+    baseline::Baseline,
+    changed::ChangedLines,
+    inventory::{count_ops, crate_modules, location, symbol_collisions, Entry, EntryKind, Inventory},
+    metrics::Metrics,
+};
+use crate::{config::Config, impls, run::cargo_check, safe::docs};
+
+pub(crate) fn run(args: crate::opts::AuditArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    crate::config::pass_to_wrapper(args.config.as_deref())?;
+    if args.metrics {
+        std::env::set_var(crate::ENV_VAR_WHYNOT_METRICS, "1");
+    }
+    if let Some(baseline) = &args.baseline {
+        crate::run::pass_path(crate::ENV_VAR_WHYNOT_BASELINE, baseline)?;
+    }
+    let parts = crate::run::parts_dir("baseline");
+    if args.save_baseline.is_some() {
+        std::env::set_var(crate::ENV_VAR_WHYNOT_SAVE_BASELINE, &parts);
+    }
+    if let Some(rev) = &args.changed_since {
+        std::env::set_var(crate::ENV_VAR_WHYNOT_CHANGED_SINCE, rev);
+    }
+    tracing::debug!("auditing");
+    let checked = cargo_check("audit", None, &args.package, Some("-Zthir-unsafeck"), rem);
+    if let Some(path) = &args.save_baseline {
+        let crates = crate::run::take_parts(&parts)?
+            .into_iter()
+            .map(|(crate_name, keys)| Ok((crate_name, serde_json::from_str(&keys)?)))
+            .collect::<Result<_>>()?;
+        Baseline::save(path, crates)?;
+        println!("saved baseline to `{}`", path.display());
+    }
+    checked
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("audit")
+    );
+    tracing::trace!("in whynot audit rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut AuditCallback::from_env()), None, &rem[1..])?;
+
+    Ok(())
+}
+
+pub struct AuditCallback {
+    metrics: bool,
+    /// Only report unsafety not in this baseline.
+    baseline: Option<PathBuf>,
+    /// Save the keys of the inventory as a part of the baseline in this directory.
+    save_baseline: Option<PathBuf>,
+    /// Only report unsafety touched by changes since this git revision.
+    changed_since: Option<String>,
+}
+
+impl AuditCallback {
+    pub fn from_env() -> Self {
+        AuditCallback {
+            metrics: std::env::var_os(crate::ENV_VAR_WHYNOT_METRICS).is_some(),
+            baseline: std::env::var_os(crate::ENV_VAR_WHYNOT_BASELINE).map(PathBuf::from),
+            save_baseline: std::env::var_os(crate::ENV_VAR_WHYNOT_SAVE_BASELINE).map(PathBuf::from),
+            changed_since: std::env::var(crate::ENV_VAR_WHYNOT_CHANGED_SINCE).ok(),
+        }
+    }
+
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let config = Config::from_env(&crate_modules(tcx))?;
+        let mut inventory = Inventory::collect(tcx);
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let metrics = Metrics::compute(tcx, &inventory);
+
+        if self.save_baseline.is_some() {
+            let keys = serde_json::to_string(&Baseline::keys(tcx, &inventory))?;
+            crate::run::write_part(
+                crate::ENV_VAR_WHYNOT_SAVE_BASELINE,
+                tcx.crate_name(LOCAL_CRATE).as_str(),
+                &keys,
+            )?;
+        }
+        let mut restrictions = vec![];
+        if let Some(path) = &self.baseline {
+            Baseline::read(path)?.filter(tcx, &mut inventory);
+            restrictions.push(format!("not in baseline `{}`", path.display()));
+        }
+        if let Some(rev) = &self.changed_since {
+            ChangedLines::from_git(rev)?.filter(tcx, &mut inventory);
+            restrictions.push(format!("touched by changes since `{rev}`"));
+        }
+        let restrictions = restrictions.join(" and ");
+        if restrictions.is_empty() {
+            print(&inventory, &mut io, tcx)?;
+        } else if inventory.entries.is_empty() {
+            writeln!(io, "no unsafety {restrictions}")?;
+        } else {
+            writeln!(io, "unsafety {restrictions}:\n")?;
+            print(&inventory, &mut io, tcx)?;
+        }
+        if let Some(path) = &self.baseline
+            && !inventory.entries.is_empty()
+        {
+            crate::run::report_failure(format!(
+                "found unsafety not in baseline `{}`",
+                path.display()
+            ))?;
+        }
+
+        if self.metrics {
+            writeln!(io)?;
+            metrics.print(&mut io)?;
+        }
+        let exceeded = metrics.check_budgets(&config.budget);
+        if !exceeded.is_empty() {
+            crate::run::report_failure(exceeded.join("\n"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Print the inventory grouped by module, with a summary of the operations in each module.
+pub fn print(inventory: &Inventory, io: &mut impl WriteColor, tcx: TyCtxt<'_>) -> Result<()> {
+    let sm = tcx.sess.source_map();
+    let by_module = inventory.by_module(tcx);
+    if by_module.is_empty() {
+        writeln!(io, "no unsafety found")?;
+        return Ok(());
+    }
+
+    let mut missing_docs = 0;
+    for (module, entries) in &by_module {
+        io.set_color(ColorSpec::new().set_bold(true))?;
+        write!(io, "module `{module}`")?;
+        io.reset()?;
+        writeln!(io)?;
+
+        for entry in entries {
+            io.set_color(ColorSpec::new().set_fg(Some(Color::Yellow)))?;
+            write!(io, "  {} `{}`", entry.kind.description(), entry.name(tcx))?;
+            io.reset()?;
+            writeln!(io, " at {}", location(sm, entry.span))?;
+
+            match entry.kind {
+                EntryKind::UnsafeFn => {
+                    if !print_fn_docs(entry, io, tcx)? {
+                        missing_docs += 1;
+                    }
+                }
+                EntryKind::UnsafeImpl { trait_id } => print_impl(entry, trait_id, io, tcx)?,
+                EntryKind::UnsafeBlock if entry.ops.is_empty() => {
+                    writeln!(io, "    unnecessary `unsafe` block")?;
+                }
+                _ => {}
+            }
+            for (kind, span) in &entry.ops {
+                writeln!(
+                    io,
+                    "    {}: {}",
+                    location(sm, *span),
+                    kind.description_and_note(tcx).0
+                )?;
+            }
+        }
+
+        let counts = count_ops(entries.iter().copied());
+        if !counts.is_empty() {
+            writeln!(io, "  operations:")?;
+            for (description, count) in counts {
+                writeln!(io, "    {count:>4} {description}")?;
+            }
+        }
+        writeln!(io)?;
+    }
+
+    print_collisions(inventory, io, tcx)?;
+
+    let count = |kind: fn(&EntryKind) -> bool| {
+        inventory
+            .entries
+            .iter()
+            .filter(|entry| kind(&entry.kind))
+            .count()
+    };
+    io.set_color(ColorSpec::new().set_bold(true))?;
+    writeln!(
+        io,
+        "{} unsafe fns, {} unsafe blocks in safe fns, {} unsafe impls, {} unsafe traits, {} items \
+         with unsafe attributes, {} extern blocks",
+        count(|kind| matches!(kind, EntryKind::UnsafeFn)),
+        count(|kind| matches!(kind, EntryKind::UnsafeBlock)),
+        count(|kind| matches!(kind, EntryKind::UnsafeImpl { .. })),
+        count(|kind| matches!(kind, EntryKind::UnsafeTrait)),
+        count(|kind| matches!(kind, EntryKind::UnsafeAttribute)),
+        count(|kind| matches!(kind, EntryKind::ExternBlock)),
+    )?;
+    io.reset()?;
+    if missing_docs > 0 {
+        writeln!(io, "{missing_docs} unsafe fns have no `# Safety` section")?;
+    }
+    Ok(())
+}
+
+/// Print why the unsafe fn of `entry` is unsafe according to its docs, returns whether it has a
+/// `# Safety` section.
+fn print_fn_docs(entry: &Entry, io: &mut impl WriteColor, tcx: TyCtxt<'_>) -> Result<bool> {
+    match docs::safety_docs(tcx, entry.def_id.to_def_id()) {
+        Some(safety) if entry.ops.is_empty() => writeln!(
+            io,
+            "    no unsafe operations, documented as unsafe because: {}",
+            safety.split_whitespace().join(" ")
+        )?,
+        Some(_) => {}
+        None => {
+            if entry.ops.is_empty() {
+                writeln!(io, "    no unsafe operations, unsafe by choice")?;
+            }
+            writeln!(io, "    no `# Safety` section in its documentation")?;
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Print what `trait_id` requires of the unsafe impl of `entry`, and the fields that may not
+/// uphold it.
+fn print_impl(
+    entry: &Entry,
+    trait_id: DefId,
+    io: &mut impl WriteColor,
+    tcx: TyCtxt<'_>,
+) -> Result<()> {
+    match impls::obligations(tcx, trait_id) {
+        Some(obligations) => writeln!(
+            io,
+            "    `{}` requires: {}",
+            tcx.item_name(trait_id),
+            obligations.split_whitespace().join(" ")
+        )?,
+        None => writeln!(
+            io,
+            "    `{}` does not document what implementors must uphold",
+            tcx.item_name(trait_id)
+        )?,
+    }
+    for field in impls::blocking_fields(tcx, entry.def_id.to_def_id(), trait_id) {
+        writeln!(
+            io,
+            "    {}: field `{}`: {}",
+            location(tcx.sess.source_map(), field.span),
+            field.name,
+            field.description(tcx, trait_id)
+        )?;
+    }
+    Ok(())
+}
+
+/// Print the symbols exported by more than one item, and by which.
+fn print_collisions(
+    inventory: &Inventory,
+    io: &mut impl WriteColor,
+    tcx: TyCtxt<'_>,
+) -> Result<()> {
+    for (symbol, entries) in symbol_collisions(tcx, &inventory.entries) {
+        io.set_color(ColorSpec::new().set_fg(Some(Color::Red)))?;
+        write!(io, "symbol `{symbol}` is exported more than once")?;
+        io.reset()?;
+        writeln!(io)?;
+        for entry in entries {
+            writeln!(
+                io,
+                "    by `{}` at {}",
+                entry.name(tcx),
+                location(tcx.sess.source_map(), entry.span)
+            )?;
+        }
+        writeln!(io)?;
+    }
+    Ok(())
+}
+
+impl rustc_driver::Callbacks for AuditCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    AuditCallback::from_env()
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
@@ -0,0 +1,162 @@
+//! Baselines of known unsafety, so that only new unsafety is reported.
+use std::{collections::BTreeMap, path::Path};
+
+use eyre::{Context, Result};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{def_id::LOCAL_CRATE, source_map::SourceMap, Span};
+use serde::{Deserialize, Serialize};
+
+use super::inventory::{Entry, EntryKind, Inventory};
+
+/// The known unsafety of every crate in a workspace.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Baseline {
+    pub crates: BTreeMap<String, Vec<Key>>,
+}
+
+/// Identifies a piece of unsafety without depending on where in the file it is.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Key {
+    /// Path of the item the unsafety is in.
+    pub path: String,
+    /// The kind of unsafe operation, or the kind of the entry if it has no operations.
+    pub kind: String,
+    /// Hash of the source of the operation, with whitespace normalised.
+    pub hash: String,
+}
+
+impl Baseline {
+    pub fn read(path: &Path) -> Result<Baseline> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("could not read baseline `{}`", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("could not parse baseline `{}`", path.display()))
+    }
+
+    /// The keys of all unsafety in the inventory of the current crate.
+    pub fn keys(tcx: TyCtxt<'_>, inventory: &Inventory) -> Vec<Key> {
+        let mut keys: Vec<_> = inventory
+            .entries
+            .iter()
+            .flat_map(|entry| entry_keys(tcx, entry).into_iter().map(|(key, _)| key))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    /// Save the keys of the crates that were checked to the baseline at `path`, keeping the other
+    /// crates already in it.
+    ///
+    /// Crates with the same name, like the library and binary of a package, are merged.
+    pub fn save(path: &Path, crates: Vec<(String, Vec<Key>)>) -> Result<()> {
+        let mut baseline = if path.exists() {
+            Baseline::read(path)?
+        } else {
+            Baseline::default()
+        };
+        let mut checked: BTreeMap<String, Vec<Key>> = BTreeMap::new();
+        for (crate_name, keys) in crates {
+            checked.entry(crate_name).or_default().extend(keys);
+        }
+        for (crate_name, mut keys) in checked {
+            keys.sort();
+            baseline.crates.insert(crate_name, keys);
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&baseline)? + "\n")
+            .wrap_err_with(|| format!("could not write baseline `{}`", path.display()))
+    }
+
+    /// Remove all unsafety in the baseline from the inventory, leaving only what was added or
+    /// changed.
+    pub fn filter(&self, tcx: TyCtxt<'_>, inventory: &mut Inventory) {
+        let mut known: BTreeMap<&Key, usize> = BTreeMap::new();
+        for key in self
+            .crates
+            .get(tcx.crate_name(LOCAL_CRATE).as_str())
+            .into_iter()
+            .flatten()
+        {
+            *known.entry(key).or_default() += 1;
+        }
+        let mut take = |key: &Key| match known.get_mut(key) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                true
+            }
+            _ => false,
+        };
+
+        inventory.entries.retain_mut(|entry| {
+            let keys = entry_keys(tcx, entry);
+            if entry.ops.is_empty() {
+                return !keys.iter().any(|(key, _)| take(key));
+            }
+            let new: Vec<_> = keys
+                .into_iter()
+                .filter(|(key, _)| !take(key))
+                .map(|(_, span)| span)
+                .collect();
+            entry.ops.retain(|(_, span)| new.contains(span));
+            !entry.ops.is_empty()
+        });
+    }
+}
+
+/// The keys for the operations in `entry`, or a key for the entry itself if it has none.
+fn entry_keys(tcx: TyCtxt<'_>, entry: &Entry) -> Vec<(Key, Span)> {
+    let sm = tcx.sess.source_map();
+    let path = entry.name(tcx);
+    if entry.ops.is_empty() {
+        let kind = match entry.kind {
+            EntryKind::UnsafeFn => "ChoosenUnsafe",
+            EntryKind::UnsafeBlock => "UnnecessaryUnsafeBlock",
+            EntryKind::UnsafeImpl { .. } => "UnsafeImpl",
+            EntryKind::UnsafeTrait => "UnsafeTrait",
+            EntryKind::UnsafeAttribute => "UnsafeAttribute",
+            EntryKind::ExternBlock => "UnsafeExternBlock",
+        };
+        return vec![(
+            Key {
+                path,
+                kind: kind.to_string(),
+                hash: snippet_hash(sm, entry.span),
+            },
+            entry.span,
+        )];
+    }
+    entry
+        .ops
+        .iter()
+        .map(|(kind, span)| {
+            (
+                Key {
+                    path: path.clone(),
+                    kind: kind.name().to_string(),
+                    hash: snippet_hash(sm, *span),
+                },
+                *span,
+            )
+        })
+        .collect()
+}
+
+/// Hash the source of `span`, ignoring differences in whitespace.
+pub fn snippet_hash(sm: &SourceMap, span: Span) -> String {
+    let snippet = sm.span_to_snippet(span).unwrap_or_default();
+    content_hash(&snippet.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// A hash that is stable between runs and toolchains (64-bit FNV-1a).
+pub fn content_hash(contents: &str) -> String {
+    let hash = contents.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
+}
+
+#[test]
+#[cfg(test)]
+fn test_content_hash() {
+    assert_eq!(content_hash(""), "cbf29ce484222325");
+    assert_eq!(content_hash("a"), "af63dc4c8601ec8c");
+}
@@ -0,0 +1,215 @@
+//! Restricting reports to lines changed since a git revision.
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use eyre::{Context, Result};
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{source_map::SourceMap, FileName, Span};
+
+use super::inventory::{EntryKind, Inventory};
+
+/// The lines changed in each file, by absolute path.
+#[derive(Debug, Default)]
+pub struct ChangedLines {
+    files: HashMap<PathBuf, Vec<RangeInclusive<usize>>>,
+}
+
+impl ChangedLines {
+    /// Diff the working tree against `rev`.
+    pub fn from_git(rev: &str) -> Result<ChangedLines> {
+        let git = |args: &[&str]| -> Result<String> {
+            let output = Command::new("git")
+                .args(args)
+                .output()
+                .wrap_err("could not run git")?;
+            eyre::ensure!(
+                output.status.success(),
+                "`git {}` failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            Ok(String::from_utf8(output.stdout)?)
+        };
+        let root = git(&["rev-parse", "--show-toplevel"])?;
+        // Source paths are canonicalized in `intersects`, so the root has to be too, or nothing
+        // matches in a checkout reached through a symlink.
+        let root = std::fs::canonicalize(root.trim())
+            .wrap_err_with(|| format!("could not find the git root `{}`", root.trim()))?;
+        let root = root.as_path();
+        let diff = git(&[
+            "diff",
+            "--unified=0",
+            "--no-color",
+            "--no-ext-diff",
+            "--src-prefix=a/",
+            "--dst-prefix=b/",
+            rev,
+        ])?;
+        let mut changed = ChangedLines::parse(root, &diff);
+        // New files that were never added don't show up in the diff, but are changed entirely.
+        let untracked = git(&[
+            "-C",
+            &root.to_string_lossy(),
+            "ls-files",
+            "--others",
+            "--exclude-standard",
+            "-z",
+        ])?;
+        for path in untracked.split('\0').filter(|path| !path.is_empty()) {
+            changed.files.insert(root.join(path), vec![1..=usize::MAX]);
+        }
+        Ok(changed)
+    }
+
+    /// Parse a unified diff with paths relative to `root`.
+    pub fn parse(root: &Path, diff: &str) -> ChangedLines {
+        let mut changed = ChangedLines::default();
+        let mut file = None;
+        for line in diff.lines() {
+            if let Some(path) = line.strip_prefix("+++ ") {
+                // Names with spaces end with a tab.
+                let path = unquote(path.trim_end_matches('\t'));
+                file = path.strip_prefix("b/").map(|path| root.join(path));
+            } else if let Some(hunk) = line.strip_prefix("@@ ")
+                && let Some(file) = &file
+            {
+                // `@@ -old,count +new,count @@`, the counts are optional and default to 1
+                let Some(new) = hunk.split(' ').find_map(|s| s.strip_prefix('+')) else {
+                    continue;
+                };
+                let (start, count) = match new.split_once(',') {
+                    Some((start, count)) => (start.parse(), count.parse()),
+                    None => (new.parse(), Ok(1)),
+                };
+                let (Ok(start), Ok(count)) = (start, count) else {
+                    continue;
+                };
+                let range: RangeInclusive<usize> = if count == 0 {
+                    // Only removed lines, which were between `start` and the line after it.
+                    start..=start + 1
+                } else {
+                    start..=start + count - 1
+                };
+                changed.files.entry(file.clone()).or_default().push(range);
+            }
+        }
+        changed
+    }
+
+    /// Whether any line of `span` was changed.
+    pub fn intersects(&self, sm: &SourceMap, span: Span) -> bool {
+        let lo = sm.lookup_char_pos(span.lo());
+        let FileName::Real(name) = &lo.file.name else {
+            return false;
+        };
+        let Some(path) = name.local_path() else {
+            return false;
+        };
+        let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+        let Some(ranges) = self.files.get(&path) else {
+            return false;
+        };
+        let lines = lo.line..=sm.lookup_char_pos(span.hi()).line;
+        ranges
+            .iter()
+            .any(|range| range.start() <= lines.end() && lines.start() <= range.end())
+    }
+
+    /// Keep only the entries where the unsafe block, fn or impl, or one of its operations, was
+    /// changed. All operations of a kept entry are kept for context.
+    pub fn filter(&self, tcx: TyCtxt<'_>, inventory: &mut Inventory) {
+        let hir = tcx.hir();
+        let sm = tcx.sess.source_map();
+        inventory.entries.retain(|entry| {
+            let span = match entry.kind {
+                EntryKind::UnsafeBlock => entry.span,
+                _ => hir.span_with_body(hir.local_def_id_to_hir_id(entry.def_id)),
+            };
+            self.intersects(sm, span)
+                || entry.ops.iter().any(|(_, span)| self.intersects(sm, *span))
+        });
+    }
+}
+
+/// Undo the quoting git does for paths with special or non-ASCII characters, `"a/\303\251.rs"`
+/// for `a/é.rs`.
+fn unquote(path: &str) -> String {
+    let Some(quoted) = path
+        .strip_prefix('"')
+        .and_then(|path| path.strip_suffix('"'))
+    else {
+        return path.to_string();
+    };
+    let mut bytes = vec![];
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            let mut buf = [0; 4];
+            bytes.extend(c.encode_utf8(&mut buf).as_bytes());
+            continue;
+        }
+        match chars.next() {
+            Some('n') => bytes.push(b'\n'),
+            Some('t') => bytes.push(b'\t'),
+            Some('r') => bytes.push(b'\r'),
+            Some('a') => bytes.push(0x07),
+            Some('b') => bytes.push(0x08),
+            Some('f') => bytes.push(0x0c),
+            Some('v') => bytes.push(0x0b),
+            Some(digit @ '0'..='7') => {
+                let octal: String = std::iter::once(digit)
+                    .chain(chars.by_ref().take(2))
+                    .collect();
+                bytes.push(u8::from_str_radix(&octal, 8).unwrap_or_default());
+            }
+            Some(c) => bytes.push(c as u8),
+            None => {}
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[test]
+#[cfg(test)]
+fn test_unquote() {
+    assert_eq!(unquote("b/src/lib.rs"), "b/src/lib.rs");
+    assert_eq!(unquote(r#""b/src/\303\251.rs""#), "b/src/é.rs");
+    assert_eq!(unquote(r#""b/src/a \"b\".rs""#), "b/src/a \"b\".rs");
+}
+
+#[test]
+#[cfg(test)]
+fn test_parse_diff() {
+    let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -3 +3 @@ pub unsafe fn foo() {
+-    let a = unsafety();
++    let a = unsafety() + 1;
+@@ -10,2 +10,0 @@ pub mod unsafe_mod {
+-        let b = 1;
+-        let c = 2;
+@@ -20,0 +19,3 @@ pub mod unsafe_mod {
++        let d = *a;
++        let e = *a;
++        let f = *a;
+diff --git a/src/removed.rs b/src/removed.rs
+deleted file mode 100644
+--- a/src/removed.rs
++++ /dev/null
+@@ -1 +0,0 @@
+-pub fn removed() {}
+";
+    let changed = ChangedLines::parse(Path::new("/repo"), diff);
+    assert_eq!(changed.files.len(), 1);
+    assert_eq!(
+        changed.files[Path::new("/repo/src/lib.rs")],
+        vec![3..=3, 10..=11, 19..=21]
+    );
+}
@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+
+use itertools::Itertools;
+
+use rustc_hir as hir;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{
+    def_id::{DefId, LocalDefId, CRATE_DEF_ID},
+    sym, Span, Symbol,
+};
+
+use crate::safe::unsafety_visitor::{self, UnsafeOpKind};
+
+/// All unsafety found in a crate.
+pub struct Inventory {
+    /// Ordered by module, and then by their position in the source.
+    pub entries: Vec<Entry>,
+}
+
+/// A single place where `unsafe` is written in the source.
+#[derive(Debug)]
+pub struct Entry {
+    pub kind: EntryKind,
+    /// The unsafe fn, the fn containing the unsafe block, the impl, the trait, the item with unsafe
+    /// attributes or the `extern` block.
+    pub def_id: LocalDefId,
+    /// The module `def_id` is defined in.
+    pub module: LocalDefId,
+    pub span: Span,
+    /// The unsafe operations done, empty for impls and traits. The unsafe attributes of an item,
+    /// and the items declared in an `extern` block.
+    pub ops: Vec<(UnsafeOpKind, Span)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    UnsafeFn,
+    /// An `unsafe {}` block in a safe function
+    UnsafeBlock,
+    UnsafeImpl {
+        trait_id: DefId,
+    },
+    UnsafeTrait,
+    /// An item with `#[no_mangle]`, `#[export_name]` or `#[link_section]`.
+    UnsafeAttribute,
+    ExternBlock,
+}
+
+impl EntryKind {
+    pub fn description(&self) -> &'static str {
+        match self {
+            EntryKind::UnsafeFn => "unsafe fn",
+            EntryKind::UnsafeBlock => "unsafe block in",
+            EntryKind::UnsafeImpl { .. } => "unsafe impl",
+            EntryKind::UnsafeTrait => "unsafe trait",
+            EntryKind::UnsafeAttribute => "unsafe attributes on",
+            EntryKind::ExternBlock => "extern block in",
+        }
+    }
+}
+
+impl Entry {
+    /// The name of the item this entry belongs to.
+    pub fn name(&self, tcx: TyCtxt<'_>) -> String {
+        match self.kind {
+            EntryKind::UnsafeImpl { trait_id } => format!(
+                "{} for {}",
+                tcx.def_path_str(trait_id),
+                tcx.type_of(self.def_id.to_def_id())
+            ),
+            EntryKind::ExternBlock => module_name(tcx, self.module),
+            _ => tcx.def_path_str(self.def_id.to_def_id()),
+        }
+    }
+}
+
+impl Inventory {
+    /// Walk every body and item in the crate, collecting all unsafety.
+    pub fn collect(tcx: TyCtxt<'_>) -> Inventory {
+        let mut entries = vec![];
+        let hir = tcx.hir();
+
+        for did in hir.body_owners() {
+            // Closures are visited together with their owner.
+            if tcx.is_closure(did.to_def_id()) {
+                continue;
+            }
+            let report = unsafety_visitor::check_body_unsafety(
+                tcx,
+                unsafety_visitor::with_opt_const_param(tcx, did),
+            );
+            let is_unsafe_fn = unsafety_visitor::is_unsafe_fn(tcx, did.to_def_id());
+            if is_unsafe_fn {
+                entries.push(Entry {
+                    kind: EntryKind::UnsafeFn,
+                    def_id: did,
+                    module: tcx.parent_module_from_def_id(did),
+                    span: tcx.def_span(did),
+                    ops: report
+                        .violations
+                        .iter()
+                        .map(|(kind, _, span)| (kind.clone(), *span))
+                        .collect(),
+                });
+            } else {
+                entries.extend(report.unsafe_blocks.into_iter().map(|block| Entry {
+                    kind: EntryKind::UnsafeBlock,
+                    def_id: did,
+                    module: tcx.parent_module_from_def_id(did),
+                    span: block.span,
+                    ops: block.ops,
+                }));
+            }
+        }
+
+        for id in hir.items() {
+            let item = hir.item(id);
+            let did = item.def_id.def_id;
+            let kind = match item.kind {
+                hir::ItemKind::Impl(hir::Impl {
+                    unsafety: hir::Unsafety::Unsafe,
+                    ..
+                }) => {
+                    let Some(trait_id) = tcx.trait_id_of_impl(did.to_def_id()) else {
+                        continue;
+                    };
+                    EntryKind::UnsafeImpl { trait_id }
+                }
+                hir::ItemKind::Trait(_, hir::Unsafety::Unsafe, ..) => EntryKind::UnsafeTrait,
+                hir::ItemKind::ForeignMod { items, .. } => {
+                    entries.push(Entry {
+                        kind: EntryKind::ExternBlock,
+                        def_id: did,
+                        module: tcx.parent_module_from_def_id(did),
+                        span: item.span,
+                        ops: items
+                            .iter()
+                            .map(|item| (UnsafeOpKind::UnsafeExternBlock, item.span))
+                            .collect(),
+                    });
+                    continue;
+                }
+                _ => continue,
+            };
+            entries.push(Entry {
+                kind,
+                def_id: did,
+                module: tcx.parent_module_from_def_id(did),
+                span: tcx.def_span(did),
+                ops: vec![],
+            });
+        }
+
+        let crate_items = tcx.hir_crate_items(());
+        let items = hir
+            .items()
+            .map(|id| id.def_id.def_id)
+            .chain(crate_items.impl_items().map(|id| id.def_id.def_id))
+            .chain(crate_items.trait_items().map(|id| id.def_id.def_id))
+            .chain(crate_items.foreign_items().map(|id| id.def_id.def_id));
+        for did in items {
+            let ops: Vec<_> = tcx
+                .get_attrs_unchecked(did.to_def_id())
+                .iter()
+                .filter_map(|attr| {
+                    let name = attr.ident()?.name;
+                    UNSAFE_ATTRIBUTES
+                        .contains(&name)
+                        .then_some((UnsafeOpKind::UnsafeAttribute(name), attr.span))
+                })
+                .collect();
+            if !ops.is_empty() {
+                entries.push(Entry {
+                    kind: EntryKind::UnsafeAttribute,
+                    def_id: did,
+                    module: tcx.parent_module_from_def_id(did),
+                    span: tcx.def_span(did),
+                    ops,
+                });
+            }
+        }
+
+        let sm = tcx.sess.source_map();
+        entries.sort_by_cached_key(|entry| {
+            let loc = sm.lookup_char_pos(entry.span.lo());
+            (
+                module_name(tcx, entry.module),
+                loc.file.name.prefer_local().to_string(),
+                loc.line,
+                loc.col,
+            )
+        });
+        Inventory { entries }
+    }
+
+    /// Entries grouped by the path of their module.
+    pub fn by_module(&self, tcx: TyCtxt<'_>) -> BTreeMap<String, Vec<&Entry>> {
+        let mut map: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for entry in &self.entries {
+            map.entry(module_name(tcx, entry.module))
+                .or_default()
+                .push(entry);
+        }
+        map
+    }
+}
+
+/// Attributes that are unsafe, as the compiler can't check the symbols and sections they create.
+const UNSAFE_ATTRIBUTES: [Symbol; 3] = [sym::no_mangle, sym::export_name, sym::link_section];
+
+/// The symbol `did` is exported as, if it is `#[no_mangle]` or has an `#[export_name]`.
+pub fn exported_symbol(tcx: TyCtxt<'_>, did: DefId) -> Option<Symbol> {
+    let attrs = tcx.get_attrs_unchecked(did);
+    attrs
+        .iter()
+        .find(|attr| attr.has_name(sym::export_name))
+        .and_then(|attr| attr.value_str())
+        .or_else(|| {
+            attrs
+                .iter()
+                .any(|attr| attr.has_name(sym::no_mangle))
+                .then(|| tcx.item_name(did))
+        })
+}
+
+/// The symbols exported by more than one item in the crate, with the entries exporting them.
+pub fn symbol_collisions<'e>(
+    tcx: TyCtxt<'_>,
+    entries: impl IntoIterator<Item = &'e Entry>,
+) -> Vec<(Symbol, Vec<&'e Entry>)> {
+    entries
+        .into_iter()
+        .filter(|entry| entry.kind == EntryKind::UnsafeAttribute)
+        .filter_map(|entry| Some((exported_symbol(tcx, entry.def_id.to_def_id())?, entry)))
+        .into_group_map()
+        .into_iter()
+        .filter(|(_, entries)| entries.len() > 1)
+        .sorted_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()))
+        .collect()
+}
+
+/// Count the operations in `entries` by their kind.
+pub fn count_ops<'e>(
+    entries: impl IntoIterator<Item = &'e Entry>,
+) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for entry in entries {
+        for (kind, _) in &entry.ops {
+            *counts.entry(kind.simple_description()).or_default() += 1;
+        }
+    }
+    counts
+}
+
+pub fn module_name(tcx: TyCtxt<'_>, module: LocalDefId) -> String {
+    let name = tcx.def_path_str(module.to_def_id());
+    if name.is_empty() {
+        "crate".to_string()
+    } else {
+        format!("crate::{name}")
+    }
+}
+
+/// The names of all modules of the crate, like `module_name` gives them.
+pub fn crate_modules(tcx: TyCtxt<'_>) -> Vec<String> {
+    let hir = tcx.hir();
+    let modules = hir
+        .items()
+        .filter(|id| matches!(hir.item(*id).kind, hir::ItemKind::Mod(_)))
+        .map(|id| id.def_id.def_id);
+    std::iter::once(CRATE_DEF_ID)
+        .chain(modules)
+        .map(|module| module_name(tcx, module))
+        .collect()
+}
+
+/// Format the start of `span` as `file:line:column`.
+pub fn location(sm: &rustc_span::source_map::SourceMap, span: Span) -> String {
+    let loc = sm.lookup_char_pos(span.lo());
+    format!(
+        "{}:{}:{}",
+        loc.file.name.prefer_local(),
+        loc.line,
+        loc.col_display + 1
+    )
+}
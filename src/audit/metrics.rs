@@ -0,0 +1,263 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::Write,
+};
+
+use eyre::Result;
+use rustc_hir as hir;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{def_id::LocalDefId, def_id::CRATE_DEF_ID, source_map::SourceMap, Span};
+use termcolor::{ColorSpec, WriteColor};
+
+use super::inventory::{module_name, EntryKind, Inventory};
+use crate::config::{strip_crate, Budget};
+
+/// Unsafe density of a module or file.
+#[derive(Debug, Default, Clone)]
+pub struct Counts {
+    /// Number of unsafe operations.
+    pub ops: usize,
+    /// Number of `unsafe {}` blocks in safe functions.
+    pub blocks: usize,
+    /// Number of lines in unsafe blocks and in the bodies of unsafe fns.
+    pub unsafe_lines: usize,
+    /// Number of unsafe attributes and items declared in extern blocks, which are not counted as
+    /// operations or lines.
+    pub declarations: usize,
+    pub total_lines: usize,
+}
+
+impl Counts {
+    /// Ratio of unsafe lines to total lines.
+    pub fn ratio(&self) -> f64 {
+        if self.total_lines == 0 {
+            0.0
+        } else {
+            self.unsafe_lines as f64 / self.total_lines as f64
+        }
+    }
+
+    /// Describe every limit in `budget` that these counts exceed.
+    pub fn exceeds(&self, budget: &Budget) -> Vec<String> {
+        let mut exceeded = vec![];
+        let mut check = |what: &str, count: usize, max: Option<usize>| {
+            if let Some(max) = max
+                && count > max
+            {
+                exceeded.push(format!("{count} {what}, budget is {max}"));
+            }
+        };
+        check("unsafe operations", self.ops, budget.max_ops);
+        check("unsafe blocks", self.blocks, budget.max_blocks);
+        check("unsafe lines", self.unsafe_lines, budget.max_lines);
+        check(
+            "unsafe attributes and extern declarations",
+            self.declarations,
+            budget.max_declarations,
+        );
+        if let Some(max) = budget.max_ratio
+            && self.ratio() > max
+        {
+            exceeded.push(format!(
+                "{:.1}% unsafe lines, budget is {:.1}%",
+                self.ratio() * 100.0,
+                max * 100.0
+            ));
+        }
+        exceeded
+    }
+}
+
+pub struct Metrics {
+    pub modules: BTreeMap<String, Counts>,
+    pub files: BTreeMap<String, Counts>,
+}
+
+type Line = (String, usize);
+
+impl Metrics {
+    pub fn compute(tcx: TyCtxt<'_>, inventory: &Inventory) -> Metrics {
+        let hir = tcx.hir();
+        let sm = tcx.sess.source_map();
+
+        // Every line belongs to the innermost module containing it.
+        let mut modules = vec![(CRATE_DEF_ID, hir.root_module().spans.inner_span)];
+        for id in hir.items() {
+            let item = hir.item(id);
+            if let hir::ItemKind::Mod(module) = item.kind {
+                modules.push((item.def_id.def_id, module.spans.inner_span));
+            }
+        }
+        modules.sort_by_key(|(_, span)| std::cmp::Reverse(span.hi() - span.lo()));
+        let mut line_owners: HashMap<Line, LocalDefId> = HashMap::new();
+        for (module, span) in modules {
+            for line in lines(sm, span) {
+                line_owners.insert(line, module);
+            }
+        }
+
+        let mut metrics = Metrics {
+            modules: BTreeMap::new(),
+            files: BTreeMap::new(),
+        };
+        for ((file, _), module) in &line_owners {
+            metrics
+                .modules
+                .entry(module_name(tcx, *module))
+                .or_default()
+                .total_lines += 1;
+            metrics.files.entry(file.clone()).or_default().total_lines += 1;
+        }
+
+        let mut unsafe_lines = BTreeSet::new();
+        for entry in &inventory.entries {
+            let module = module_name(tcx, entry.module);
+            let span = match entry.kind {
+                EntryKind::UnsafeFn => {
+                    hir.span_with_body(hir.local_def_id_to_hir_id(entry.def_id))
+                }
+                EntryKind::UnsafeBlock => {
+                    metrics.modules.entry(module.clone()).or_default().blocks += 1;
+                    if let Some((file, _)) = lines(sm, entry.span).next() {
+                        metrics.files.entry(file).or_default().blocks += 1;
+                    }
+                    entry.span
+                }
+                EntryKind::UnsafeAttribute | EntryKind::ExternBlock => {
+                    metrics.modules.entry(module).or_default().declarations += entry.ops.len();
+                    for (_, span) in &entry.ops {
+                        if let Some((file, _)) = lines(sm, *span).next() {
+                            metrics.files.entry(file).or_default().declarations += 1;
+                        }
+                    }
+                    continue;
+                }
+                EntryKind::UnsafeImpl { .. } | EntryKind::UnsafeTrait => continue,
+            };
+            unsafe_lines.extend(lines(sm, span));
+
+            metrics.modules.entry(module).or_default().ops += entry.ops.len();
+            for (_, span) in &entry.ops {
+                if let Some((file, _)) = lines(sm, *span).next() {
+                    metrics.files.entry(file).or_default().ops += 1;
+                }
+            }
+        }
+        for line in unsafe_lines {
+            if let Some(module) = line_owners.get(&line) {
+                metrics
+                    .modules
+                    .entry(module_name(tcx, *module))
+                    .or_default()
+                    .unsafe_lines += 1;
+            }
+            metrics.files.entry(line.0).or_default().unsafe_lines += 1;
+        }
+        metrics
+    }
+
+    /// Check the module budgets, returning a description of every exceeded budget.
+    pub fn check_budgets(&self, budgets: &BTreeMap<String, Budget>) -> Vec<String> {
+        let mut exceeded = vec![];
+        for (module, budget) in budgets {
+            let counts = self.module_totals(module);
+            for reason in counts.exceeds(budget) {
+                exceeded.push(format!(
+                    "module `{module}` exceeds its unsafe budget: {reason}"
+                ));
+            }
+        }
+        exceeded
+    }
+
+    /// The counts of `module` together with those of all its submodules.
+    pub fn module_totals(&self, module: &str) -> Counts {
+        let mut totals = Counts::default();
+        for (_, counts) in self
+            .modules
+            .iter()
+            .filter(|(name, _)| is_within(name, module))
+        {
+            totals.ops += counts.ops;
+            totals.blocks += counts.blocks;
+            totals.unsafe_lines += counts.unsafe_lines;
+            totals.total_lines += counts.total_lines;
+            totals.declarations += counts.declarations;
+        }
+        totals
+    }
+
+    pub fn print(&self, io: &mut impl WriteColor) -> Result<()> {
+        print_table(io, "module", &self.modules)?;
+        writeln!(io)?;
+        print_table(io, "file", &self.files)
+    }
+}
+
+fn print_table(
+    io: &mut impl WriteColor,
+    header: &str,
+    rows: &BTreeMap<String, Counts>,
+) -> Result<()> {
+    let width = rows
+        .keys()
+        .map(|name| name.len())
+        .chain([header.len()])
+        .max()
+        .unwrap_or_default();
+    io.set_color(ColorSpec::new().set_bold(true))?;
+    write!(
+        io,
+        "{header:width$} {:>6} {:>6} {:>6} {:>6} {:>7} {:>6}",
+        "ops", "blocks", "lines", "total", "ratio", "decls"
+    )?;
+    io.reset()?;
+    writeln!(io)?;
+    for (name, counts) in rows {
+        writeln!(
+            io,
+            "{name:width$} {:>6} {:>6} {:>6} {:>6} {:>6.1}% {:>6}",
+            counts.ops,
+            counts.blocks,
+            counts.unsafe_lines,
+            counts.total_lines,
+            counts.ratio() * 100.0,
+            counts.declarations
+        )?;
+    }
+    Ok(())
+}
+
+/// Whether `name` is the module `module` or one of its submodules, where the `crate::` prefix
+/// is optional.
+fn is_within(name: &str, module: &str) -> bool {
+    let (name, module) = (strip_crate(name), strip_crate(module));
+    module.is_empty()
+        || name
+            .strip_prefix(module)
+            .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// The lines `span` covers, empty if the span is not in a file of this crate.
+fn lines(sm: &SourceMap, span: Span) -> impl Iterator<Item = Line> {
+    let range = (!sm.is_imported(span) && !span.is_dummy()).then(|| {
+        let lo = sm.lookup_char_pos(span.lo());
+        let hi = sm.lookup_char_pos(span.hi());
+        (lo.file.name.prefer_local().to_string(), lo.line..=hi.line)
+    });
+    range
+        .into_iter()
+        .flat_map(|(file, lines)| lines.map(move |line| (file.clone(), line)))
+}
+
+#[test]
+#[cfg(test)]
+fn test_is_within() {
+    assert!(is_within("crate::ffi", "crate::ffi"));
+    assert!(is_within("crate::ffi::sys", "crate::ffi"));
+    assert!(!is_within("crate::ffi_utils", "crate::ffi"));
+    assert!(is_within("crate::logic", "crate"));
+    assert!(is_within("crate::ffi::sys", "ffi"));
+    assert!(is_within("crate", "crate"));
+    assert!(!is_within("crate", "ffi"));
+}
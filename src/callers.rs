@@ -0,0 +1,301 @@
+//! Find the paths through the call graph from safe functions to an unsafe fn, and where each
+//! crosses from safe into unsafe code.
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    io::Write,
+};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::{Context, Result};
+use itertools::Itertools;
+use rustc_hir as hir;
+use rustc_middle::{
+    thir::{
+        visit::{self, Visitor},
+        *,
+    },
+    ty::{self, TyCtxt},
+};
+use rustc_span::{
+    def_id::{DefId, LocalDefId},
+    Span,
+};
+
+use crate::{
+    report::Files,
+    run::cargo_check,
+    safe::{unsafety_visitor, FakeCallback},
+};
+
+pub(crate) fn run(args: crate::opts::CallersArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    if args.all {
+        std::env::set_var(crate::ENV_VAR_WHYNOT_ALL_CALLERS, "1");
+    }
+    tracing::debug!("finding callers of {}", args.item);
+    cargo_check(
+        "callers",
+        Some(args.item.to_string()),
+        &args.package,
+        Some("-Zthir-unsafeck"),
+        rem,
+    )
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("callers")
+    );
+    tracing::trace!("in whynot callers rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut CallersCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+/// The calls between local functions, closures are part of the function they are in.
+#[derive(Debug, Default)]
+pub struct CallGraph {
+    /// For every local fn, the local fns calling it and the span of each call.
+    callers: HashMap<LocalDefId, Vec<(LocalDefId, Span)>>,
+}
+
+impl CallGraph {
+    pub fn build(tcx: TyCtxt<'_>) -> CallGraph {
+        let mut graph = CallGraph::default();
+        for did in tcx.hir().body_owners() {
+            if tcx.is_closure(did.to_def_id()) {
+                continue;
+            }
+            let mut calls = vec![];
+            calls_in(tcx, did, &mut calls);
+            for (callee, span) in calls {
+                if let Some(callee) = callee.as_local() {
+                    graph.callers.entry(callee).or_default().push((did, span));
+                }
+            }
+        }
+        graph
+    }
+
+    pub fn callers(&self, did: LocalDefId) -> &[(LocalDefId, Span)] {
+        self.callers.get(&did).map_or(&[], Vec::as_slice)
+    }
+
+    /// The fns that are an entry point or are called from one, directly or indirectly.
+    pub fn reached_from(&self, is_entry: impl Fn(LocalDefId) -> bool) -> HashSet<LocalDefId> {
+        let mut callees: HashMap<LocalDefId, Vec<LocalDefId>> = HashMap::new();
+        for (callee, callers) in &self.callers {
+            for (caller, _) in callers {
+                callees.entry(*caller).or_default().push(*callee);
+            }
+        }
+        let mut reached = HashSet::new();
+        let mut stack: Vec<_> = callees.keys().copied().filter(|did| is_entry(*did)).collect();
+        while let Some(did) = stack.pop() {
+            if reached.insert(did) {
+                stack.extend(callees.get(&did).into_iter().flatten().copied());
+            }
+        }
+        reached
+    }
+}
+
+/// All calls to fn items in the body of `did`, and in the closures in it.
+pub fn calls_in(tcx: TyCtxt<'_>, did: LocalDefId, calls: &mut Vec<(DefId, Span)>) {
+    struct Calls<'a, 'tcx> {
+        tcx: TyCtxt<'tcx>,
+        thir: &'a Thir<'tcx>,
+        param_env: ty::ParamEnv<'tcx>,
+        calls: &'a mut Vec<(DefId, Span)>,
+    }
+
+    impl<'a, 'tcx> Visitor<'a, 'tcx> for Calls<'a, 'tcx> {
+        fn thir(&self) -> &'a Thir<'tcx> {
+            self.thir
+        }
+
+        fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+            match expr.kind {
+                ExprKind::Call { fun, .. } => {
+                    if let &ty::FnDef(callee, substs) = self.thir[fun].ty.kind() {
+                        let callee = unsafety_visitor::resolve_callee(
+                            self.tcx,
+                            self.param_env,
+                            callee,
+                            substs,
+                        );
+                        self.calls.push((callee, expr.span));
+                    }
+                }
+                ExprKind::Closure(box ClosureExpr { closure_id, .. }) => {
+                    calls_in(self.tcx, closure_id, self.calls);
+                }
+                _ => {}
+            }
+            visit::walk_expr(self, expr);
+        }
+    }
+
+    let Ok((thir, expr)) = tcx.thir_body(unsafety_visitor::with_opt_const_param(tcx, did)) else {
+        return;
+    };
+    let thir = &thir.borrow();
+    if thir.exprs.is_empty() {
+        return;
+    }
+    Calls {
+        tcx,
+        thir,
+        param_env: tcx.param_env(did),
+        calls,
+    }
+    .visit_expr(&thir[expr]);
+}
+
+/// Stop before the number of paths through the call graph explodes.
+const MAX_PATHS: usize = 1000;
+/// Stop exploring partial paths after this many, even if few of them reached an entry point.
+const MAX_EXPLORED: usize = 100_000;
+
+pub struct CallersCallback;
+
+impl CallersCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let selector = std::env::var(crate::ENV_VAR_WHYNOT_SELECTOR)
+            .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?;
+        let all = std::env::var_os(crate::ENV_VAR_WHYNOT_ALL_CALLERS).is_some();
+        let (target, _) = FakeCallback { selector }.search(tcx)?;
+        let graph = CallGraph::build(tcx);
+        let exported = tcx.effective_visibilities(());
+        let is_entry = |did: LocalDefId| {
+            !unsafety_visitor::is_unsafe_fn(tcx, did.to_def_id())
+                && (all || exported.is_exported(did))
+        };
+
+        // Walk the call graph backwards from the target, every path is stored from the target up.
+        // Callers no entry point reaches can't lead to one, so they are not explored.
+        let reached = graph.reached_from(&is_entry);
+        let mut paths = vec![];
+        let mut truncated = false;
+        let mut explored = 0;
+        let mut stack = vec![vec![(target, None)]];
+        while let Some(path) = stack.pop() {
+            explored += 1;
+            let (did, _) = *path.last().unwrap();
+            if path.len() > 1 && is_entry(did) {
+                if paths.len() == MAX_PATHS {
+                    truncated = true;
+                    break;
+                }
+                paths.push(path.clone());
+            }
+            if explored == MAX_EXPLORED {
+                truncated = !stack.is_empty() || !graph.callers(did).is_empty();
+                break;
+            }
+            for &(caller, span) in graph.callers(did) {
+                if reached.contains(&caller) && !path.iter().any(|(d, _)| *d == caller) {
+                    let mut path = path.clone();
+                    path.push((caller, Some(span)));
+                    stack.push(path);
+                }
+            }
+        }
+        paths.sort_by_cached_key(|path| {
+            path.iter()
+                .rev()
+                .map(|(did, call)| (tcx.def_path_str(did.to_def_id()), call.map(|s| s.lo())))
+                .collect::<Vec<_>>()
+        });
+
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut files = Files::new(tcx.sess.source_map());
+        let mut reports = HashMap::new();
+        for path in &paths {
+            let message = path
+                .iter()
+                .rev()
+                .map(|(did, _)| format!("`{}`", tcx.def_path_str(did.to_def_id())))
+                .join(" -> ");
+            let mut labels = vec![];
+            // Each step is `(caller, the call to the previous step)`.
+            for ((callee, _), (caller, call)) in path.iter().tuple_windows() {
+                let call = call.expect("only the target has no call");
+                if unsafety_visitor::is_unsafe_fn(tcx, caller.to_def_id())
+                    || !unsafety_visitor::is_unsafe_fn(tcx, callee.to_def_id())
+                {
+                    continue;
+                }
+                let report = reports.entry(*caller).or_insert_with(|| {
+                    unsafety_visitor::check_body_unsafety(
+                        tcx,
+                        unsafety_visitor::with_opt_const_param(tcx, *caller),
+                    )
+                });
+                if let Some(block) = report
+                    .unsafe_blocks
+                    .iter()
+                    .find(|block| block.span.contains(call))
+                {
+                    labels.extend(files.label(
+                        block.span,
+                        LabelStyle::Primary,
+                        "the safe boundary is crossed in this unsafe block",
+                    ));
+                }
+                labels.extend(files.label(
+                    call,
+                    LabelStyle::Secondary,
+                    format!("call to `{}`", tcx.def_path_str(callee.to_def_id())),
+                ));
+            }
+            files.emit(
+                &mut io,
+                &Diagnostic::note().with_message(message).with_labels(labels),
+            )?;
+        }
+        writeln!(
+            io,
+            "{} paths from {} to `{}`",
+            paths.len(),
+            if all {
+                "safe functions"
+            } else {
+                "exported safe functions"
+            },
+            tcx.def_path_str(target.to_def_id())
+        )?;
+        if truncated {
+            writeln!(
+                io,
+                "stopped searching early, more paths may lead to `{}`",
+                tcx.def_path_str(target.to_def_id())
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl rustc_driver::Callbacks for CallersCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    CallersCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
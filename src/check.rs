@@ -0,0 +1,161 @@
+use std::{ffi::OsString, io::Write};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::Result;
+use rustc_middle::ty::TyCtxt;
+
+use crate::{
+    audit::{
+        inventory::{crate_modules, module_name, symbol_collisions, EntryKind, Inventory},
+        metrics::Metrics,
+    },
+    config::{Config, Level},
+    report::Files,
+    run::cargo_check,
+    safe::{inherited_unsafety, unsafety_visitor::UnsafeOpKind},
+};
+
+pub(crate) fn run(args: crate::opts::CheckArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    crate::config::pass_to_wrapper(args.config.as_deref())?;
+    tracing::debug!("checking policy");
+    cargo_check("check", None, &args.package, Some("-Zthir-unsafeck"), rem)
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("check")
+    );
+    tracing::trace!("in whynot check rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut CheckCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+pub struct CheckCallback;
+
+impl CheckCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let config = Config::from_env(&crate_modules(tcx))?;
+        let inventory = Inventory::collect(tcx);
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut files = Files::new(tcx.sess.source_map());
+
+        let mut denied = 0;
+        let mut warned = 0;
+        for entry in &inventory.entries {
+            let module = module_name(tcx, entry.module);
+            let (context, ops) = match entry.kind {
+                EntryKind::UnsafeFn if entry.ops.is_empty() => {
+                    let kind = inherited_unsafety(tcx, entry.def_id)
+                        .map_or(UnsafeOpKind::ChoosenUnsafe, |(kind, _)| kind);
+                    ("", vec![(kind, entry.span)])
+                }
+                EntryKind::UnsafeFn => ("in this unsafe fn", entry.ops.clone()),
+                EntryKind::UnsafeBlock => ("in this unsafe block", entry.ops.clone()),
+                EntryKind::UnsafeAttribute => ("on this item", entry.ops.clone()),
+                EntryKind::ExternBlock => ("in this extern block", entry.ops.clone()),
+                EntryKind::UnsafeImpl { .. } | EntryKind::UnsafeTrait => continue,
+            };
+            for (kind, span) in ops {
+                let Some((level, glob)) = config.policy_level(&module, kind.name()) else {
+                    continue;
+                };
+                let diag = match level {
+                    Level::Allow => continue,
+                    Level::Warn => {
+                        warned += 1;
+                        Diagnostic::warning()
+                    }
+                    Level::Deny => {
+                        denied += 1;
+                        Diagnostic::error()
+                    }
+                };
+                let mut labels = vec![];
+                labels.extend(files.label(span, LabelStyle::Primary, kind.simple_description()));
+                if span != entry.span {
+                    labels.extend(files.label(entry.span, LabelStyle::Secondary, context));
+                }
+                let diag = diag
+                    .with_message(format!(
+                        "{} is {} in `{module}`",
+                        kind.description_and_note(tcx).0,
+                        level.past_tense()
+                    ))
+                    .with_labels(labels)
+                    .with_notes(vec![format!(
+                        "`{}` is set to `{}` for `{glob}` in the policy",
+                        kind.name(),
+                        level.as_str()
+                    )]);
+                files.emit(&mut io, &diag)?;
+            }
+        }
+
+        let collisions = symbol_collisions(tcx, &inventory.entries);
+        for (symbol, entries) in &collisions {
+            let labels = entries
+                .iter()
+                .filter_map(|entry| {
+                    files.label(
+                        entry.span,
+                        LabelStyle::Primary,
+                        format!("`{}` exports `{symbol}`", entry.name(tcx)),
+                    )
+                })
+                .collect();
+            files.emit(
+                &mut io,
+                &Diagnostic::error()
+                    .with_message(format!("symbol `{symbol}` is exported more than once"))
+                    .with_labels(labels),
+            )?;
+        }
+
+        let exceeded = Metrics::compute(tcx, &inventory).check_budgets(&config.budget);
+        for budget in &exceeded {
+            files.emit(&mut io, &Diagnostic::error().with_message(budget))?;
+        }
+
+        writeln!(
+            io,
+            "{denied} denied unsafe operations, {warned} warnings, {} symbol collisions, {} exceeded \
+             budgets",
+            collisions.len(),
+            exceeded.len()
+        )?;
+        if denied > 0 || !collisions.is_empty() || !exceeded.is_empty() {
+            crate::run::report_failure(format!(
+                "unsafe policy check failed: {denied} denied unsafe operations, {} symbol \
+                 collisions, {} exceeded budgets",
+                collisions.len(),
+                exceeded.len()
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl rustc_driver::Callbacks for CheckCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    CheckCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
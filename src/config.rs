@@ -0,0 +1,269 @@
+//! Configuration read from `whynot.toml`
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use eyre::{Context, Result};
+use serde::Deserialize;
+
+pub static CONFIG_FILE_NAME: &str = "whynot.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Limits on the unsafety allowed in a module, keyed by the path of the module, e.g.
+    /// `crate::sys`. The `crate::` prefix is optional.
+    #[serde(default)]
+    pub budget: BTreeMap<String, Budget>,
+    /// What to do with each kind of unsafe operation, keyed by a glob of module paths, e.g.
+    /// `arch::*`. The most specific glob that sets a kind applies.
+    #[serde(default)]
+    pub policy: BTreeMap<String, BTreeMap<String, Level>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Allow => "allow",
+            Level::Warn => "warn",
+            Level::Deny => "deny",
+        }
+    }
+
+    pub fn past_tense(&self) -> &'static str {
+        match self {
+            Level::Allow => "allowed",
+            Level::Warn => "warned against",
+            Level::Deny => "denied",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Budget {
+    /// Maximum number of unsafe operations.
+    pub max_ops: Option<usize>,
+    /// Maximum number of `unsafe {}` blocks.
+    pub max_blocks: Option<usize>,
+    /// Maximum number of lines in unsafe blocks and unsafe fns.
+    pub max_lines: Option<usize>,
+    /// Maximum ratio of unsafe lines to total lines, between `0.0` and `1.0`.
+    pub max_ratio: Option<f64>,
+    /// Maximum number of unsafe attributes and items in extern blocks.
+    pub max_declarations: Option<usize>,
+}
+
+impl Config {
+    /// Load the config at `path`, or the config of the package currently being compiled, and
+    /// check it against the `modules` of the crate.
+    ///
+    /// The package config is `whynot.toml` next to `Cargo.toml`, or `[package.metadata.whynot]`
+    /// in `Cargo.toml`. A missing package config gives the default config, but an explicitly
+    /// given path must exist.
+    pub fn load(path: Option<&Path>, modules: &[String]) -> Result<Config> {
+        let config = match path {
+            Some(path) => Config::read(path)?,
+            None => {
+                let Some(manifest_dir) = std::env::var_os("CARGO_MANIFEST_DIR") else {
+                    return Ok(Config::default());
+                };
+                let path = PathBuf::from(manifest_dir).join(CONFIG_FILE_NAME);
+                if path.exists() {
+                    Config::read(&path)?
+                } else {
+                    Config::from_manifest(&path.with_file_name("Cargo.toml"))?
+                }
+            }
+        };
+        config.validate(modules)?;
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Config> {
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("could not read config `{}`", path.display()))?;
+        toml::from_str(&contents)
+            .wrap_err_with(|| format!("could not parse config `{}`", path.display()))
+    }
+
+    /// Read the config from `[package.metadata.whynot]`
+    fn from_manifest(path: &Path) -> Result<Config> {
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            return Ok(Config::default());
+        };
+        let manifest: toml::Value = toml::from_str(&contents)
+            .wrap_err_with(|| format!("could not parse manifest `{}`", path.display()))?;
+        match manifest
+            .get("package")
+            .and_then(|p| p.get("metadata"))
+            .and_then(|m| m.get("whynot"))
+        {
+            Some(whynot) => whynot.clone().try_into().wrap_err_with(|| {
+                format!(
+                    "could not parse `[package.metadata.whynot]` in `{}`",
+                    path.display()
+                )
+            }),
+            None => Ok(Config::default()),
+        }
+    }
+
+    fn validate(&self, modules: &[String]) -> Result<()> {
+        use crate::safe::unsafety_visitor::UnsafeOpKind;
+        for module in self.budget.keys() {
+            eyre::ensure!(
+                modules
+                    .iter()
+                    .any(|name| strip_crate(name) == strip_crate(module)),
+                "budget for `{module}` names no module in the crate"
+            );
+        }
+        for (glob, kinds) in &self.policy {
+            for kind in kinds.keys() {
+                eyre::ensure!(
+                    UnsafeOpKind::NAMES.contains(&kind.as_str()),
+                    "unknown unsafe operation `{kind}` in policy for `{glob}`, expected one of: {}",
+                    UnsafeOpKind::NAMES.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The level set for `kind` in `module`, and the glob that set it.
+    pub fn policy_level(&self, module: &str, kind: &str) -> Option<(Level, &str)> {
+        self.policy
+            .iter()
+            .filter(|(glob, _)| glob_matches(glob, module))
+            .filter_map(|(glob, kinds)| Some((*kinds.get(kind)?, glob.as_str())))
+            .max_by_key(|(level, glob)| (glob.chars().filter(|c| *c != '*').count(), *level))
+    }
+
+    /// Load the config passed on from `cargo whynot` to the rustc wrapper.
+    pub fn from_env(modules: &[String]) -> Result<Config> {
+        let path = std::env::var_os(crate::ENV_VAR_WHYNOT_CONFIG).map(PathBuf::from);
+        Config::load(path.as_deref(), modules)
+    }
+}
+
+/// Pass the config path on to the rustc wrapper, it runs in another working directory.
+pub fn pass_to_wrapper(path: Option<&Path>) -> Result<()> {
+    if let Some(path) = path {
+        let path = std::fs::canonicalize(path)
+            .wrap_err_with(|| format!("could not find config `{}`", path.display()))?;
+        std::env::set_var(crate::ENV_VAR_WHYNOT_CONFIG, path);
+    }
+    Ok(())
+}
+
+/// The module path without its optional `crate::` prefix, empty for the crate root.
+pub fn strip_crate(path: &str) -> &str {
+    if path == "crate" {
+        ""
+    } else {
+        path.strip_prefix("crate::").unwrap_or(path)
+    }
+}
+
+/// Match a module path against a glob, where `*` matches any part of the path.
+///
+/// The `crate::` prefix is optional in both, and `foo::*` also matches `foo` itself.
+pub fn glob_matches(glob: &str, path: &str) -> bool {
+    fn matches(glob: &[u8], path: &[u8]) -> bool {
+        match glob.split_first() {
+            None => path.is_empty(),
+            Some((b'*', rest)) => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+            Some((c, rest)) => path.first() == Some(c) && matches(rest, &path[1..]),
+        }
+    }
+
+    let (glob, path) = (strip_crate(glob), strip_crate(path));
+    if let Some(module) = glob.strip_suffix("::*")
+        && matches(module.as_bytes(), path.as_bytes())
+    {
+        return true;
+    }
+    matches(glob.as_bytes(), path.as_bytes())
+}
+
+#[test]
+#[cfg(test)]
+fn test_glob() {
+    assert!(glob_matches("*", "crate"));
+    assert!(glob_matches("*", "crate::foo::bar"));
+    assert!(glob_matches("arch::*", "crate::arch"));
+    assert!(glob_matches("crate::arch::*", "crate::arch::x86"));
+    assert!(glob_matches("arch::*::simd", "arch::x86::simd"));
+    assert!(!glob_matches("arch::*", "crate::archive"));
+    assert!(!glob_matches("arch", "crate::arch::x86"));
+}
+
+#[test]
+#[cfg(test)]
+fn test_policy() {
+    let config: Config = toml::from_str(
+        r#"
+        [policy."*"]
+        UseOfMutableStatic = "deny"
+        UseOfInlineAssembly = "deny"
+
+        [policy."arch::*"]
+        UseOfInlineAssembly = "allow"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.policy_level("crate::arch::x86", "UseOfInlineAssembly"),
+        Some((Level::Allow, "arch::*"))
+    );
+    assert_eq!(
+        config.policy_level("crate::arch::x86", "UseOfMutableStatic"),
+        Some((Level::Deny, "*"))
+    );
+    assert_eq!(
+        config.policy_level("crate::net", "UseOfInlineAssembly"),
+        Some((Level::Deny, "*"))
+    );
+    assert_eq!(config.policy_level("crate", "DerefOfRawPointer"), None);
+}
+
+#[test]
+#[cfg(test)]
+fn test_budget() {
+    let config: Config = toml::from_str(
+        r#"
+        [budget."crate::sys"]
+        max-ops = 10
+        max-ratio = 0.5
+
+        [budget."crate::logic"]
+        max-blocks = 0
+        max-declarations = 0
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.budget["crate::sys"].max_ops, Some(10));
+    assert_eq!(config.budget["crate::sys"].max_ratio, Some(0.5));
+    assert_eq!(config.budget["crate::logic"].max_blocks, Some(0));
+    assert_eq!(config.budget["crate::logic"].max_declarations, Some(0));
+    assert!(toml::from_str::<Config>("[budget.foo]\nmax-unsafe = 1").is_err());
+
+    let modules = ["crate".to_string(), "crate::sys".to_string(), "crate::logic".to_string()];
+    assert!(config.validate(&modules).is_ok());
+    let config: Config = toml::from_str("[budget.sys]\nmax-ops = 1").unwrap();
+    assert!(config.validate(&modules).is_ok());
+    let config: Config = toml::from_str("[budget.crate]\nmax-ops = 1").unwrap();
+    assert!(config.validate(&modules).is_ok());
+    let config: Config = toml::from_str("[budget.system]\nmax-ops = 1").unwrap();
+    assert!(config.validate(&modules).is_err());
+}
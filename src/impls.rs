@@ -0,0 +1,267 @@
+//! Explain `unsafe impl`s: what the trait requires, and why the impl had to be written by hand.
+use std::{collections::HashSet, ffi::OsString, io::Write};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::{Context, Result};
+use itertools::Itertools;
+use rustc_infer::infer::{InferCtxt, TyCtxtInferExt};
+use rustc_middle::ty::{
+    self,
+    fast_reject::{simplify_type, TreatParams},
+    Ty, TyCtxt,
+};
+use rustc_span::{def_id::DefId, Span, Symbol, DUMMY_SP};
+use rustc_trait_selection::traits::type_known_to_meet_bound_modulo_regions;
+
+use crate::{
+    audit::inventory::{EntryKind, Inventory},
+    report::Files,
+    run::cargo_check,
+    safe::docs,
+};
+
+pub(crate) fn run(args: crate::opts::ImplArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    std::env::set_var(crate::ENV_VAR_WHYNOT_TRAIT, &args.trait_);
+    tracing::debug!("explaining unsafe impl {} for {}", args.trait_, args.ty);
+    cargo_check(
+        "impl",
+        Some(args.ty),
+        &args.package,
+        Some("-Zthir-unsafeck"),
+        rem,
+    )
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("impl")
+    );
+    tracing::trace!("in whynot impl rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut ImplCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+/// A field keeping an auto trait from being implemented automatically for its type.
+pub struct BlockingField<'tcx> {
+    pub name: Symbol,
+    pub span: Span,
+    pub ty: Ty<'tcx>,
+    /// The type in `ty` that opts out of the trait, e.g. a raw pointer.
+    pub culprit: Ty<'tcx>,
+}
+
+impl BlockingField<'_> {
+    pub fn description(&self, tcx: TyCtxt<'_>, trait_id: DefId) -> String {
+        let trait_ = tcx.item_name(trait_id);
+        if self.ty == self.culprit {
+            format!("`{}` is not `{trait_}`", self.ty)
+        } else {
+            format!("contains `{}`, which is not `{trait_}`", self.culprit)
+        }
+    }
+}
+
+/// The fields of the type of `impl_id` that make a manual impl of the auto trait necessary.
+///
+/// Empty for traits that are not auto traits, as those always have to be implemented by hand.
+pub fn blocking_fields(tcx: TyCtxt<'_>, impl_id: DefId, trait_id: DefId) -> Vec<BlockingField<'_>> {
+    if !tcx.trait_is_auto(trait_id) {
+        return vec![];
+    }
+    let &ty::Adt(adt, substs) = tcx.type_of(impl_id).kind() else {
+        return vec![];
+    };
+    let infcx = tcx.infer_ctxt().build();
+    let solver = AutoTrait {
+        infcx: &infcx,
+        param_env: tcx.param_env(impl_id),
+        trait_id,
+    };
+    adt.all_fields()
+        .filter_map(|field| {
+            let ty = field.ty(tcx, substs);
+            let culprit = solver.opted_out(ty, &mut HashSet::new())?;
+            Some(BlockingField {
+                name: field.name,
+                span: tcx.def_span(field.did),
+                ty,
+                culprit,
+            })
+        })
+        .collect()
+}
+
+/// Asks the trait solver whether types implement an auto trait, under the where clauses of the
+/// impl being explained.
+struct AutoTrait<'a, 'tcx> {
+    infcx: &'a InferCtxt<'tcx>,
+    param_env: ty::ParamEnv<'tcx>,
+    trait_id: DefId,
+}
+
+impl<'tcx> AutoTrait<'_, 'tcx> {
+    fn implements(&self, ty: Ty<'tcx>) -> bool {
+        type_known_to_meet_bound_modulo_regions(
+            self.infcx,
+            self.param_env,
+            ty,
+            self.trait_id,
+            DUMMY_SP,
+        )
+    }
+
+    /// The type in `ty` that keeps it from implementing the trait, `None` if it does implement
+    /// it. Looks into the type arguments first, e.g. `*mut u8` in `Vec<*mut u8>`, and then into
+    /// the fields, stopping at types with a negative impl.
+    fn opted_out(&self, ty: Ty<'tcx>, seen: &mut HashSet<Ty<'tcx>>) -> Option<Ty<'tcx>> {
+        if !seen.insert(ty) || self.implements(ty) {
+            return None;
+        }
+        if self.has_negative_impl(ty) {
+            return Some(ty);
+        }
+        let tcx = self.infcx.tcx;
+        let inner = match *ty.kind() {
+            ty::Adt(adt, substs) => substs
+                .types()
+                .find_map(|arg| self.opted_out(arg, seen))
+                .or_else(|| {
+                    adt.all_fields()
+                        .find_map(|field| self.opted_out(field.ty(tcx, substs), seen))
+                }),
+            ty::Array(ty, _) | ty::Slice(ty) | ty::Ref(_, ty, _) => self.opted_out(ty, seen),
+            ty::Tuple(tys) => tys.iter().find_map(|ty| self.opted_out(ty, seen)),
+            _ => None,
+        };
+        Some(inner.unwrap_or(ty))
+    }
+
+    fn has_negative_impl(&self, ty: Ty<'tcx>) -> bool {
+        let tcx = self.infcx.tcx;
+        let Some(simplified) = simplify_type(tcx, ty, TreatParams::AsPlaceholder) else {
+            return false;
+        };
+        tcx.all_impls(self.trait_id).any(|impl_| {
+            tcx.impl_polarity(impl_) == ty::ImplPolarity::Negative
+                && simplify_type(tcx, tcx.type_of(impl_), TreatParams::AsPlaceholder)
+                    == Some(simplified)
+        })
+    }
+}
+
+/// What implementors of the trait must uphold, the `# Safety` section of its documentation, or
+/// its summary if there is none.
+pub fn obligations(tcx: TyCtxt<'_>, trait_id: DefId) -> Option<String> {
+    docs::safety_docs(tcx, trait_id).or_else(|| docs::summary(tcx, trait_id))
+}
+
+/// The path of the self type of an impl, without generic arguments.
+fn self_type_path(tcx: TyCtxt<'_>, impl_id: DefId) -> String {
+    match tcx.type_of(impl_id).kind() {
+        ty::Adt(adt, _) => tcx.def_path_str(adt.did()),
+        ty => ty.to_string(),
+    }
+}
+
+/// Whether `path` is `query`, or ends with it.
+fn matches_path(path: &str, query: &str) -> bool {
+    let query = query.strip_prefix("crate::").unwrap_or(query);
+    path == query || path.ends_with(&format!("::{query}"))
+}
+
+pub struct ImplCallback;
+
+impl ImplCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let ty = std::env::var(crate::ENV_VAR_WHYNOT_SELECTOR)
+            .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?;
+        let trait_ = std::env::var(crate::ENV_VAR_WHYNOT_TRAIT)
+            .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?;
+
+        let impls = Inventory::collect(tcx)
+            .entries
+            .into_iter()
+            .filter_map(|entry| match entry.kind {
+                EntryKind::UnsafeImpl { trait_id } => Some((entry, trait_id)),
+                _ => None,
+            })
+            .filter(|(entry, trait_id)| {
+                matches_path(&self_type_path(tcx, entry.def_id.to_def_id()), &ty)
+                    && matches_path(&tcx.def_path_str(*trait_id), &trait_)
+            })
+            .collect_vec();
+        eyre::ensure!(
+            !impls.is_empty(),
+            "no `unsafe impl {trait_} for {ty}` found"
+        );
+
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut files = Files::new(tcx.sess.source_map());
+        for (entry, trait_id) in impls {
+            let trait_path = tcx.def_path_str(trait_id);
+            let mut labels = vec![];
+            labels.extend(files.label(entry.span, LabelStyle::Primary, "unsafe impl"));
+            let fields = blocking_fields(tcx, entry.def_id.to_def_id(), trait_id);
+            for field in &fields {
+                labels.extend(files.label(
+                    field.span,
+                    LabelStyle::Secondary,
+                    field.description(tcx, trait_id),
+                ));
+            }
+            let mut notes = vec![match obligations(tcx, trait_id) {
+                Some(obligations) => format!("`{trait_path}` requires:\n{obligations}"),
+                None => format!("`{trait_path}` does not document what implementors must uphold"),
+            }];
+            if !fields.is_empty() {
+                notes.push(format!(
+                    "`{}` would not be `{trait_path}` automatically because of the fields {}",
+                    tcx.type_of(entry.def_id.to_def_id()),
+                    fields.iter().map(|field| format!("`{}`", field.name)).join(", ")
+                ));
+            }
+            files.emit(
+                &mut io,
+                &Diagnostic::note()
+                    .with_message(format!("unsafe impl `{}`", entry.name(tcx)))
+                    .with_labels(labels)
+                    .with_notes(notes),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl rustc_driver::Callbacks for ImplCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    ImplCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
+
+#[test]
+#[cfg(test)]
+fn test_matches_path() {
+    assert!(matches_path("std::marker::Send", "Send"));
+    assert!(matches_path("std::marker::Send", "marker::Send"));
+    assert!(matches_path("buf::RawBuf", "crate::buf::RawBuf"));
+    assert!(!matches_path("buf::MyRawBuf", "RawBuf"));
+}
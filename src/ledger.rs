@@ -0,0 +1,396 @@
+//! A ledger of which unsafe code has been reviewed, and by whom.
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::{Context, Result};
+use itertools::Itertools;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::def_id::LOCAL_CRATE;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    audit::{
+        baseline::content_hash,
+        inventory::{Entry, EntryKind, Inventory},
+    },
+    opts::LedgerCommand,
+    report::Files,
+    run::cargo_check,
+};
+
+pub(crate) fn run(args: crate::opts::LedgerArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    crate::run::pass_path(crate::ENV_VAR_WHYNOT_LEDGER, &args.ledger)?;
+    let parts = crate::run::parts_dir("ledger");
+    let selector = match args.command {
+        LedgerCommand::Sign { item, reviewer } => {
+            std::env::set_var(crate::ENV_VAR_WHYNOT_REVIEWER, reviewer);
+            std::env::set_var(crate::ENV_VAR_WHYNOT_LEDGER_PARTS, &parts);
+            Some(item.to_string())
+        }
+        LedgerCommand::Verify { reviewers } => {
+            std::env::set_var(crate::ENV_VAR_WHYNOT_REVIEWERS, reviewers.to_string());
+            None
+        }
+    };
+    tracing::debug!("checking ledger");
+    let checked = cargo_check(
+        "ledger",
+        selector.clone(),
+        &args.package,
+        Some("-Zthir-unsafeck"),
+        rem,
+    );
+    let parts = crate::run::take_parts(&parts)?
+        .into_iter()
+        .map(|(crate_name, part)| Ok((crate_name, serde_json::from_str::<Part>(&part)?)))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(selector) = selector
+        && checked.is_ok()
+    {
+        eyre::ensure!(
+            parts.iter().any(|(_, part)| part.signed > 0),
+            "no unsafe code found in `{selector}`"
+        );
+        let mut ledger = Ledger::read(&args.ledger)?;
+        for (crate_name, parts) in &parts.into_iter().group_by(|(name, _)| name.clone()) {
+            ledger.merge(crate_name, parts.map(|(_, part)| part.records));
+        }
+        ledger.write(&args.ledger)?;
+    }
+    checked
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("ledger")
+    );
+    tracing::trace!("in whynot ledger rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut LedgerCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+/// The reviews of every crate in a workspace.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Ledger {
+    pub crates: BTreeMap<String, Vec<Record>>,
+}
+
+/// The reviews of a single unsafe fn or block, in the version with `hash`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Record {
+    /// Path of the unsafe fn, or the fn the unsafe block is in.
+    pub path: String,
+    pub kind: RecordKind,
+    /// Hash of the source of the fn or block and the unsafe operations found in it.
+    pub hash: String,
+    /// The unsafe operations found when it was reviewed.
+    pub reasons: Vec<String>,
+    pub reviews: Vec<Review>,
+}
+
+/// The records of a crate after signing, written by the rustc wrapper for `cargo whynot` to merge.
+#[derive(Debug, Serialize, Deserialize)]
+struct Part {
+    /// How many fns and blocks were signed, `0` if the item is not in the crate.
+    signed: usize,
+    records: Vec<Record>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RecordKind {
+    UnsafeFn,
+    UnsafeBlock,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Review {
+    pub reviewer: String,
+    /// Seconds since the unix epoch.
+    pub signed_at: u64,
+}
+
+impl Ledger {
+    pub fn read(path: &Path) -> Result<Ledger> {
+        if !path.exists() {
+            return Ok(Ledger::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("could not read ledger `{}`", path.display()))?;
+        serde_json::from_str(&contents)
+            .wrap_err_with(|| format!("could not parse ledger `{}`", path.display()))
+    }
+
+    /// Replace the records of `crate_name` with the signed records of each of its crates.
+    ///
+    /// Crates with the same name, like the library and binary of a package, are signed
+    /// separately, so the reviews of records in several of them are combined.
+    pub fn merge(&mut self, crate_name: String, signed: impl IntoIterator<Item = Vec<Record>>) {
+        let mut merged: Vec<Record> = vec![];
+        for record in signed.into_iter().flatten() {
+            match merged.iter_mut().find(|r| r.is_same(&record)) {
+                Some(existing) => {
+                    for review in record.reviews {
+                        if !existing.reviews.iter().any(|r| r.reviewer == review.reviewer) {
+                            existing.reviews.push(review);
+                        }
+                    }
+                }
+                None => merged.push(record),
+            }
+        }
+        merged.sort_by(|a, b| (&a.path, &a.hash).cmp(&(&b.path, &b.hash)));
+        self.crates.insert(crate_name, merged);
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+            .wrap_err_with(|| format!("could not write ledger `{}`", path.display()))
+    }
+}
+
+impl Record {
+    /// Records with no reviews for the current versions of the fns and blocks in `inventory`.
+    ///
+    /// Blocks with the same source in the same fn are told apart by their position among them.
+    pub fn for_inventory<'a>(
+        tcx: TyCtxt<'_>,
+        inventory: &'a Inventory,
+    ) -> Vec<(&'a Entry, Record)> {
+        let mut occurrences = HashMap::new();
+        inventory
+            .entries
+            .iter()
+            .filter_map(|entry| Some((entry, Record::for_entry(tcx, entry, &mut occurrences)?)))
+            .collect()
+    }
+
+    /// A record with no reviews for the current version of `entry`, if it is a fn or block.
+    ///
+    /// `occurrences` counts the entries with the same path and source seen so far.
+    fn for_entry(
+        tcx: TyCtxt<'_>,
+        entry: &Entry,
+        occurrences: &mut HashMap<String, usize>,
+    ) -> Option<Record> {
+        let hir = tcx.hir();
+        let (kind, span) = match entry.kind {
+            EntryKind::UnsafeFn => (
+                RecordKind::UnsafeFn,
+                hir.span_with_body(hir.local_def_id_to_hir_id(entry.def_id)),
+            ),
+            EntryKind::UnsafeBlock => (RecordKind::UnsafeBlock, entry.span),
+            EntryKind::UnsafeImpl { .. }
+            | EntryKind::UnsafeTrait
+            | EntryKind::UnsafeAttribute
+            | EntryKind::ExternBlock => return None,
+        };
+        let source = tcx.sess.source_map().span_to_snippet(span).ok()?;
+        let mut reasons: Vec<_> = entry
+            .ops
+            .iter()
+            .map(|(kind, _)| kind.description_and_note(tcx).0.into_owned())
+            .collect();
+        reasons.sort();
+        let mut kinds: Vec<_> = entry.ops.iter().map(|(kind, _)| kind.name()).collect();
+        kinds.sort_unstable();
+        // Only what identifies the code goes into the hash, not the descriptions of the
+        // operations, so that rewording them doesn't invalidate every review.
+        let path = entry.name(tcx);
+        let content = format!(
+            "{kind:?}\n{path}\n{}\n{}",
+            source.split_whitespace().collect::<Vec<_>>().join(" "),
+            kinds.join("\n")
+        );
+        let occurrence = occurrences.entry(content.clone()).or_default();
+        // The first occurrence keeps the hash it had before it got an identical sibling.
+        let hash = match *occurrence {
+            0 => content_hash(&content),
+            n => content_hash(&format!("{content}\n{n}")),
+        };
+        *occurrence += 1;
+        Some(Record {
+            path,
+            kind,
+            hash,
+            reasons,
+            reviews: vec![],
+        })
+    }
+
+    fn is_same(&self, other: &Record) -> bool {
+        self.path == other.path && self.kind == other.kind && self.hash == other.hash
+    }
+}
+
+pub struct LedgerCallback;
+
+impl LedgerCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let path = PathBuf::from(
+            std::env::var_os(crate::ENV_VAR_WHYNOT_LEDGER)
+                .ok_or_else(|| eyre::eyre!(crate::WHYNOT_RUSTC_WRAPPER_ERROR))?,
+        );
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut ledger = Ledger::read(&path)?;
+        let inventory = Inventory::collect(tcx);
+        let crate_name = tcx.crate_name(LOCAL_CRATE).to_string();
+        let records = ledger.crates.entry(crate_name).or_default();
+
+        if let Ok(reviewer) = std::env::var(crate::ENV_VAR_WHYNOT_REVIEWER) {
+            let selector = std::env::var(crate::ENV_VAR_WHYNOT_SELECTOR)
+                .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?;
+            // Every crate writes its part, `cargo whynot` fails if none of them signed anything.
+            let signed = sign(tcx, &inventory, records, &selector, &reviewer, &mut io)?;
+            crate::run::write_part(
+                crate::ENV_VAR_WHYNOT_LEDGER_PARTS,
+                tcx.crate_name(LOCAL_CRATE).as_str(),
+                &serde_json::to_string(&Part {
+                    signed,
+                    records: records.clone(),
+                })?,
+            )?;
+        } else {
+            let reviewers: usize = std::env::var(crate::ENV_VAR_WHYNOT_REVIEWERS)
+                .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?
+                .parse()?;
+            verify(tcx, &inventory, records, reviewers, &mut io)?;
+        }
+        Ok(())
+    }
+}
+
+/// Sign all unsafe fns and blocks in items matching `selector`, dropping the records of versions
+/// of them that no longer exist. Returns how many were signed.
+fn sign(
+    tcx: TyCtxt<'_>,
+    inventory: &Inventory,
+    records: &mut Vec<Record>,
+    selector: &str,
+    reviewer: &str,
+    io: &mut impl Write,
+) -> Result<usize> {
+    let signed_at = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let current: Vec<_> = Record::for_inventory(tcx, inventory)
+        .into_iter()
+        .filter(|(_, record)| record.path.ends_with(selector))
+        .map(|(_, record)| record)
+        .collect();
+
+    let mut signed = vec![];
+    for mut record in current {
+        let previous = records.iter().position(|r| r.is_same(&record));
+        if let Some(previous) = previous {
+            record.reviews = records.remove(previous).reviews;
+        }
+        if !record.reviews.iter().any(|r| r.reviewer == reviewer) {
+            record.reviews.push(Review {
+                reviewer: reviewer.to_string(),
+                signed_at,
+            });
+        }
+        writeln!(
+            io,
+            "signed {} `{}` ({} reviews)",
+            match record.kind {
+                RecordKind::UnsafeFn => "unsafe fn",
+                RecordKind::UnsafeBlock => "unsafe block in",
+            },
+            record.path,
+            record.reviews.len()
+        )?;
+        signed.push(record);
+    }
+    // Records of earlier versions of what was just signed are outdated.
+    records.retain(|r| !signed.iter().any(|s| s.path == r.path && s.kind == r.kind));
+    let count = signed.len();
+    records.extend(signed);
+    records.sort_by(|a, b| (&a.path, &a.hash).cmp(&(&b.path, &b.hash)));
+    Ok(count)
+}
+
+/// Report all unsafe fns and blocks that are new, changed or don't have enough reviews.
+fn verify(
+    tcx: TyCtxt<'_>,
+    inventory: &Inventory,
+    records: &[Record],
+    reviewers: usize,
+    io: &mut impl termcolor::WriteColor,
+) -> Result<()> {
+    let mut files = Files::new(tcx.sess.source_map());
+    let mut unreviewed = 0;
+    for (entry, current) in Record::for_inventory(tcx, inventory) {
+        let what = format!("{} `{}`", entry.kind.description(), current.path);
+        let message = match records.iter().find(|r| r.is_same(&current)) {
+            Some(record) if record.reviews.len() >= reviewers => continue,
+            Some(record) => format!(
+                "{what} has {} of {reviewers} required reviews",
+                record.reviews.len()
+            ),
+            None if records
+                .iter()
+                .any(|r| r.path == current.path && r.kind == current.kind) =>
+            {
+                format!("{what} has changed since it was last reviewed")
+            }
+            None => format!("{what} has not been reviewed"),
+        };
+        unreviewed += 1;
+        let mut diag = Diagnostic::error().with_message(message).with_labels(
+            files
+                .label(entry.span, LabelStyle::Primary, "")
+                .into_iter()
+                .collect(),
+        );
+        if !current.reasons.is_empty() {
+            diag = diag.with_notes(vec![format!(
+                "unsafe operations: {}",
+                current.reasons.join(", ")
+            )]);
+        }
+        files.emit(io, &diag)?;
+    }
+
+    if unreviewed > 0 {
+        writeln!(
+            io,
+            "{unreviewed} unsafe fns or blocks need review, sign them with `cargo whynot ledger sign <ITEM> --reviewer <NAME>`"
+        )?;
+        crate::run::report_failure(format!("{unreviewed} unsafe fns or blocks need review"))?;
+    } else {
+        writeln!(io, "all unsafe code has been reviewed")?;
+    }
+    Ok(())
+}
+
+impl rustc_driver::Callbacks for LedgerCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    LedgerCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
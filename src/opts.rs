@@ -1,6 +1,7 @@
 use clap::Parser;
 use std::ffi::OsString;
 use std::fmt::{self, Display};
+use std::path::PathBuf;
 use std::str::FromStr;
 use syn_select::Selector;
 
@@ -19,19 +20,197 @@ pub enum SubCommand {
     /// Find the reason for why a function is not safe.
     #[clap(name = "safe", version)]
     Safe(Args),
+    /// List all unsafety in the crate, grouped by module.
+    #[clap(name = "audit", version)]
+    Audit(AuditArgs),
+    /// Check the crate against the unsafe policy and budgets in `whynot.toml`.
+    #[clap(name = "check", version)]
+    Check(CheckArgs),
+    /// Record and verify reviews of unsafe code.
+    #[clap(name = "ledger", version)]
+    Ledger(LedgerArgs),
+    /// Check that unsafe blocks and operations have `// SAFETY:` comments.
+    #[clap(name = "safety-comments", version)]
+    SafetyComments(SafetyCommentsArgs),
+    /// Estimate how much unsafety making an unsafe fn safe would remove.
+    #[clap(name = "what-if", version)]
+    WhatIf(WhatIfArgs),
+    /// Find the paths from safe functions to an unsafe fn.
+    #[clap(name = "callers", version)]
+    Callers(CallersArgs),
+    /// Explain an `unsafe impl` of a trait for a type.
+    #[clap(name = "impl", version)]
+    Impl(ImplArgs),
+    /// List every `static mut` and extern static, where they are used and what could replace them.
+    #[clap(name = "statics", version)]
+    Statics(StaticsArgs),
+    /// List every union, where its fields are written and read, and likely type punning.
+    #[clap(name = "unions", version)]
+    Unions(UnionsArgs),
+    /// List every `asm!` block with its options, operands and clobbers, and how it is guarded.
+    #[clap(name = "asm", version)]
+    Asm(AsmArgs),
 }
 
 #[derive(Parser, Debug)]
+#[clap(group(clap::ArgGroup::new("target").required(true).args(["item", "candidates"])))]
 pub struct Args {
     /// Local path to workspace function to check.
     #[clap(value_name = "ITEM", value_parser = crate::parse_selector)]
-    pub item: Selector,
+    pub item: Option<Selector>,
     #[clap(long, short = 'p')]
     pub package: Option<String>,
+    /// List the unsafe fns in the crate that could be made safe, instead of checking one.
+    #[clap(long)]
+    pub candidates: bool,
     #[clap(default_value = "always")]
     pub color: Coloring,
 }
 
+#[derive(Parser, Debug)]
+pub struct AuditArgs {
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    /// Print unsafe density metrics per module and per file.
+    #[clap(long)]
+    pub metrics: bool,
+    /// Only report unsafety added or changed since this baseline, failing if there is any.
+    #[clap(long, value_name = "FILE")]
+    pub baseline: Option<PathBuf>,
+    /// Save the current unsafety as a baseline.
+    #[clap(long, value_name = "FILE")]
+    pub save_baseline: Option<PathBuf>,
+    /// Only report unsafety whose operations, block or fn were changed since this git revision.
+    #[clap(long, value_name = "REV")]
+    pub changed_since: Option<String>,
+    /// Path to the config file, defaults to `whynot.toml` in the package.
+    #[clap(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub struct CheckArgs {
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    /// Path to the config file, defaults to `whynot.toml` in the package.
+    #[clap(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub struct LedgerArgs {
+    #[clap(subcommand)]
+    pub command: LedgerCommand,
+    #[clap(long, short = 'p', global = true)]
+    pub package: Option<String>,
+    /// Path to the ledger file.
+    #[clap(
+        long,
+        value_name = "FILE",
+        default_value = "whynot-ledger.json",
+        global = true
+    )]
+    pub ledger: PathBuf,
+    #[clap(long, default_value = "always", global = true)]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub enum LedgerCommand {
+    /// Record a review of the unsafe fns and blocks in an item.
+    #[clap(name = "sign")]
+    Sign {
+        /// Local path to workspace item that was reviewed.
+        #[clap(value_name = "ITEM", value_parser = crate::parse_selector)]
+        item: Selector,
+        /// Name of the reviewer.
+        #[clap(long)]
+        reviewer: String,
+    },
+    /// Report unsafe fns and blocks that are new or changed since they were last reviewed.
+    #[clap(name = "verify")]
+    Verify {
+        /// Number of reviews required for every unsafe fn and block.
+        #[clap(long, default_value = "1")]
+        reviewers: usize,
+    },
+}
+
+#[derive(Parser, Debug)]
+pub struct SafetyCommentsArgs {
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub struct WhatIfArgs {
+    /// Local path to the workspace function to treat as safe.
+    #[clap(long, value_name = "ITEM", value_parser = crate::parse_selector)]
+    pub make_safe: Selector,
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub struct CallersArgs {
+    /// Local path to the workspace function to find the callers of.
+    #[clap(value_name = "ITEM", value_parser = crate::parse_selector)]
+    pub item: Selector,
+    /// Start from every safe function, not only those exported from the crate.
+    #[clap(long)]
+    pub all: bool,
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub struct ImplArgs {
+    /// Path to the type the trait is implemented for, e.g. `buf::RawBuf`.
+    #[clap(value_name = "TYPE")]
+    pub ty: String,
+    /// Path to the unsafe trait, e.g. `Send`.
+    #[clap(value_name = "TRAIT")]
+    pub trait_: String,
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub struct StaticsArgs {
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub struct UnionsArgs {
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
+#[derive(Parser, Debug)]
+pub struct AsmArgs {
+    #[clap(long, short = 'p')]
+    pub package: Option<String>,
+    #[clap(long, default_value = "always")]
+    pub color: Coloring,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum OutputMode {
     Normal,
@@ -0,0 +1,75 @@
+//! Helpers for printing diagnostics pointing into the crate's source.
+use std::collections::HashMap;
+
+use codespan_reporting::{
+    diagnostic::{Diagnostic, Label, LabelStyle},
+    files::SimpleFiles,
+};
+use eyre::Result;
+use rustc_span::{source_map::SourceMap, Span};
+
+/// The files of the source map that diagnostics have pointed into.
+pub struct Files<'s> {
+    files: SimpleFiles<String, String>,
+    /// Maps a source map file index to a file id in `files`.
+    ids: HashMap<usize, usize>,
+    source_map: &'s SourceMap,
+}
+
+impl<'s> Files<'s> {
+    pub fn new(source_map: &'s SourceMap) -> Self {
+        Files {
+            files: SimpleFiles::new(),
+            ids: HashMap::new(),
+            source_map,
+        }
+    }
+
+    /// Make a label for `span`, adding its file if needed.
+    ///
+    /// Returns `None` if the source of the file isn't available.
+    pub fn label(
+        &mut self,
+        span: Span,
+        style: LabelStyle,
+        message: impl Into<String>,
+    ) -> Option<Label<usize>> {
+        let idx = self.source_map.lookup_source_file_idx(span.lo());
+        let id = match self.ids.get(&idx) {
+            Some(id) => *id,
+            None => {
+                let file = self.source_map.files()[idx].clone();
+                let src = file.src.clone()?;
+                let id = self
+                    .files
+                    .add(file.name.prefer_local().to_string(), (*src).clone());
+                self.ids.insert(idx, id);
+                id
+            }
+        };
+        Some(crate::safe::span_label(
+            id,
+            self.source_map,
+            span,
+            style,
+            Some(message.into()),
+        ))
+    }
+
+    pub fn emit(
+        &self,
+        io: &mut impl termcolor::WriteColor,
+        diagnostic: &Diagnostic<usize>,
+    ) -> Result<()> {
+        codespan_reporting::term::emit(
+            io,
+            &codespan_reporting::term::Config {
+                display_style: codespan_reporting::term::DisplayStyle::Rich,
+                ..<_>::default()
+            },
+            &self.files,
+            diagnostic,
+        )?;
+        Ok(())
+    }
+}
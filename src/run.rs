@@ -1,7 +1,10 @@
 use eyre::Result;
 use rustc_codegen_ssa::traits::CodegenBackend;
 use rustc_session::config;
-use std::{ffi::OsStr, path::PathBuf};
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 
 /// Invoke cargo check, but, set RUSTC_WORKSPACE_WRAPPER to this binary
 pub fn cargo_check<T: AsRef<OsStr>>(
@@ -30,8 +33,86 @@ pub fn cargo_check<T: AsRef<OsStr>>(
     if let Some(selector) = command_selector {
         cmd.env(crate::ENV_VAR_WHYNOT_SELECTOR, selector);
     }
+    let failure_file =
+        std::env::temp_dir().join(format!("cargo-whynot-{}.failure", std::process::id()));
+    cmd.env(crate::ENV_VAR_WHYNOT_FAILURE, &failure_file);
     // cmd.stdout(std::process::Stdio::null());
     cmd.status()?;
+    if let Ok(failure) = std::fs::read_to_string(&failure_file) {
+        let _ = std::fs::remove_file(&failure_file);
+        eyre::bail!("{}", failure.trim_end());
+    }
+    Ok(())
+}
+
+/// Pass a path on to the rustc wrapper, it runs in another working directory.
+pub fn pass_path(var: &str, path: &Path) -> Result<()> {
+    std::env::set_var(var, std::env::current_dir()?.join(path));
+    Ok(())
+}
+
+/// A fresh directory for the rustc wrappers to write their parts of the file `name` to, see
+/// [`write_part`].
+pub fn parts_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cargo-whynot-{}.{name}", std::process::id()))
+}
+
+/// Write the part of a file shared between crates for the crate being compiled, to the directory
+/// passed in `var`.
+///
+/// Cargo runs the wrappers for the crates of a workspace in parallel, so they can't update the
+/// shared file themselves without losing each other's changes. Instead `cargo whynot` merges the
+/// parts with [`take_parts`] once cargo is done.
+pub fn write_part(var: &str, crate_name: &str, contents: &str) -> Result<()> {
+    let dir = PathBuf::from(
+        std::env::var_os(var).ok_or_else(|| eyre::eyre!(crate::WHYNOT_RUSTC_WRAPPER_ERROR))?,
+    );
+    std::fs::create_dir_all(&dir)?;
+    let name = format!("{crate_name}-{}", std::process::id());
+    // Write to a temporary file first, so that a part is never read half written.
+    let tmp = dir.join(format!("{name}.tmp"));
+    std::fs::write(&tmp, contents)?;
+    std::fs::rename(&tmp, dir.join(format!("{name}.json")))?;
+    Ok(())
+}
+
+/// Read and remove the parts written to `dir` by [`write_part`], with the name of the crate
+/// of each, sorted by crate.
+pub fn take_parts(dir: &Path) -> Result<Vec<(String, String)>> {
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let mut parts = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() != Some(OsStr::new("json")) {
+            continue;
+        }
+        let stem = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+        // Crate names can't contain `-`, so the last one separates the process id.
+        let crate_name = stem.rsplit_once('-').map_or(stem.as_str(), |(name, _)| name);
+        parts.push((crate_name.to_string(), std::fs::read_to_string(&path)?));
+    }
+    std::fs::remove_dir_all(dir)?;
+    parts.sort();
+    Ok(parts)
+}
+
+/// Make `cargo whynot` exit with an error once cargo is done, e.g. when a budget is exceeded.
+///
+/// Called from the rustc wrapper, which can't exit successfully without producing any output.
+pub fn report_failure(message: impl std::fmt::Display) -> Result<()> {
+    use std::io::Write;
+    let path = std::env::var_os(crate::ENV_VAR_WHYNOT_FAILURE)
+        .ok_or_else(|| eyre::eyre!(crate::WHYNOT_RUSTC_WRAPPER_ERROR))?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{message}")?;
     Ok(())
 }
 
@@ -1,24 +1,40 @@
+pub mod candidates;
+pub mod dataflow;
+pub mod docs;
 pub mod expr;
+pub mod obligations;
+pub mod provenance;
+pub mod target_features;
 pub mod unsafety_visitor;
 
-use std::{collections::VecDeque, ffi::OsString};
+use std::{
+    collections::{HashMap, VecDeque},
+    ffi::OsString,
+};
 
 use codespan_reporting::diagnostic::{Diagnostic, Label, LabelStyle};
 use eyre::{Context, Result};
 use hir::{def_id::LocalDefId, intravisit::Visitor, FnHeader};
 use itertools::Itertools;
 use rustc_hir as hir;
-use rustc_middle::ty::{self, TyCtxt};
-use rustc_span::Span;
+use rustc_middle::ty::{self, Ty, TyCtxt};
+use rustc_span::{def_id::DefId, Span};
 
-use crate::{run::cargo_check, safe::unsafety_visitor::UnsafeOpKind};
+use crate::{
+    audit::inventory::location,
+    run::cargo_check,
+    safe::{dataflow::Dependency, provenance::Provenance, unsafety_visitor::UnsafeOpKind},
+};
 
 pub(crate) fn run(args: crate::opts::Args, rem: &[String]) -> Result<()> {
     std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    if args.candidates {
+        std::env::set_var(crate::ENV_VAR_WHYNOT_CANDIDATES, "1");
+    }
     tracing::debug!("checking");
     cargo_check(
         "safe",
-        Some(args.item.to_string()),
+        args.item.map(|item| item.to_string()),
         &args.package,
         Some("-Zthir-unsafeck"),
         rem,
@@ -30,12 +46,17 @@ pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
         std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
         Ok("safe")
     );
+    tracing::trace!("in whynot safe rustc with rem: `{rem:?}`");
+    if std::env::var_os(crate::ENV_VAR_WHYNOT_CANDIDATES).is_some() {
+        crate::run::rustc_run(Some(&mut CandidatesCallback), None, &rem[1..])?;
+        return Ok(());
+    }
+
     let selector = std::env::var(crate::ENV_VAR_WHYNOT_SELECTOR)
         .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?;
 
     let _ = crate::parse_selector(&selector)?;
     tracing::trace!("in whynot safe rustc with selector: {selector:?}");
-    tracing::trace!("in whynot safe rustc with rem: `{rem:?}`");
 
     crate::run::rustc_run(Some(&mut FakeCallback { selector }), None, &rem[1..])?;
 
@@ -82,6 +103,7 @@ impl SafeOutput<'_> {
             }
         }
 
+        let coercions = provenance::fn_coercions(tcx);
         let mut labels = vec![];
         let mut first = true;
         // why is this unique needed?
@@ -104,11 +126,61 @@ impl SafeOutput<'_> {
             ));
             let mut primary_reason = false;
             let mut primary_reason_is_extern = true;
+            let mut choosen_unsafe = None;
+            let mut inherited = None;
+            let mut callees = vec![];
+            let mut feature_calls = vec![];
+            let flow = dataflow::classify(tcx, unsafety_visitor::with_opt_const_param(tcx, did));
+            let mut dependencies = vec![];
+            let provenances =
+                provenance::trace_derefs(tcx, unsafety_visitor::with_opt_const_param(tcx, did));
+            let fn_pointers = provenance::trace_fn_pointer_calls(
+                tcx,
+                unsafety_visitor::with_opt_const_param(tcx, did),
+            );
+            let mut fn_pointer_types = vec![];
             for (reason, _, span) in reasons {
                 let idx = self.source_map.lookup_source_file_idx(span.lo());
+                if let UnsafeOpKind::CallToUnsafeFunction(Some(callee)) = reason
+                    && !callees.contains(&callee)
+                {
+                    callees.push(callee);
+                }
+                if let UnsafeOpKind::CallToFunctionWith(callee, missing) = &reason
+                    && !feature_calls.iter().any(|(c, m)| c == callee && m == missing)
+                {
+                    feature_calls.push((*callee, missing.clone()));
+                }
                 let label = match reason {
+                    UnsafeOpKind::InheritedFromInterface(_) => {
+                        let (description, note) = reason.description_and_note(tcx);
+                        inherited = Some(note);
+                        span_label(
+                            hash_map[&idx],
+                            self.source_map,
+                            span,
+                            LabelStyle::Primary,
+                            Some(description.to_string()),
+                        )
+                    }
+                    UnsafeOpKind::ChoosenUnsafe => {
+                        let safety = docs::safety_docs(tcx, did.to_def_id());
+                        let message = if safety.is_some() {
+                            "unsafe by choice, see its `# Safety` section"
+                        } else {
+                            reason.simple_description()
+                        };
+                        choosen_unsafe = Some(safety);
+                        span_label(
+                            hash_map[&idx],
+                            self.source_map,
+                            span,
+                            LabelStyle::Primary,
+                            Some(message.to_string()),
+                        )
+                    }
                     UnsafeOpKind::CallToUnsafeFunction(Some(did))
-                    | UnsafeOpKind::CallToFunctionWith(did)
+                    | UnsafeOpKind::CallToFunctionWith(did, _)
                         if did.as_local().is_some() =>
                     {
                         span_label(
@@ -122,7 +194,7 @@ impl SafeOutput<'_> {
                     _ => {
                         primary_reason = true;
                         if let UnsafeOpKind::CallToUnsafeFunction(Some(_))
-                        | UnsafeOpKind::CallToFunctionWith(_) = reason
+                        | UnsafeOpKind::CallToFunctionWith(..) = reason
                         {
                         } else {
                             primary_reason_is_extern = false;
@@ -132,11 +204,39 @@ impl SafeOutput<'_> {
                             self.source_map,
                             span,
                             LabelStyle::Primary,
-                            Some(reason.simple_description().to_string()),
+                            Some(reason.description_and_note(tcx).0.to_string()),
                         )
                     }
                 };
+                let label = match flow.get(&span) {
+                    Some(dependency)
+                        if !matches!(
+                            reason,
+                            UnsafeOpKind::ChoosenUnsafe | UnsafeOpKind::InheritedFromInterface(_)
+                        ) =>
+                    {
+                        dependencies.push(*dependency);
+                        let message = format!("{} ({})", label.message, dependency.description());
+                        label.with_message(message)
+                    }
+                    _ => label,
+                };
                 labels.push(label);
+                labels.extend(provenance_label(
+                    tcx,
+                    self.source_map,
+                    hash_map[&idx],
+                    &reason,
+                    span,
+                    &provenances,
+                    &fn_pointers,
+                ));
+                if let UnsafeOpKind::CallToUnsafeFunction(None) = reason
+                    && let Some((_, ty)) = fn_pointers.get(&span)
+                    && !fn_pointer_types.contains(ty)
+                {
+                    fn_pointer_types.push(*ty);
+                }
             }
 
             let mut diag = if first {
@@ -148,7 +248,12 @@ impl SafeOutput<'_> {
                 Diagnostic::help().with_labels(labels.clone())
             };
 
-            if primary_reason {
+            if let Some(note) = inherited {
+                diag = diag.with_notes(vec![note.to_string()]);
+            } else if let Some(safety) = choosen_unsafe {
+                let notes = contract_notes(tcx, self.source_map, did, safety, &coercions);
+                diag = diag.with_notes(notes);
+            } else if primary_reason {
                 if primary_reason_is_extern {
                     diag = diag.with_notes(vec![
                         "this function calls an external unsafe function".to_string(),
@@ -159,6 +264,14 @@ impl SafeOutput<'_> {
                     ]);
                 }
             }
+            diag.notes.extend(dependency_note(tcx, did, &dependencies));
+            diag.notes.extend(fn_pointer_notes(tcx, &coercions, &fn_pointer_types));
+            for callee in callees {
+                diag.notes.extend(callee_notes(tcx, callee));
+            }
+            for (callee, missing) in feature_calls {
+                diag.notes.extend(target_features::explain(tcx, did, callee, &missing));
+            }
             codespan_reporting::term::emit(
                 &mut io,
                 &codespan_reporting::term::Config {
@@ -180,6 +293,148 @@ impl SafeOutput<'_> {
     }
 }
 
+/// A label pointing at where the pointer dereferenced or called at `span` comes from.
+fn provenance_label<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    sm: &rustc_span::source_map::SourceMap,
+    file: usize,
+    reason: &UnsafeOpKind,
+    span: Span,
+    provenances: &HashMap<Span, Provenance>,
+    fn_pointers: &HashMap<Span, (Option<Provenance>, Ty<'tcx>)>,
+) -> Option<Label<usize>> {
+    let (what, provenance) = match reason {
+        UnsafeOpKind::DerefOfRawPointer(_) => ("pointer", provenances.get(&span)?),
+        UnsafeOpKind::CallToUnsafeFunction(None) => {
+            ("fn pointer", fn_pointers.get(&span)?.0.as_ref()?)
+        }
+        _ => return None,
+    };
+    Some(span_label(
+        file,
+        sm,
+        provenance.span(),
+        LabelStyle::Secondary,
+        Some(format!("{what} comes from {}", provenance.description(tcx))),
+    ))
+}
+
+/// Notes on the safety contract of `did`, which is unsafe by choice, and whether its
+/// `unsafe` is needed for the fn pointers it is coerced to.
+fn contract_notes<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    sm: &rustc_span::source_map::SourceMap,
+    did: LocalDefId,
+    safety: Option<String>,
+    coercions: &[(DefId, Ty<'tcx>, Span)],
+) -> Vec<String> {
+    let mut notes = vec![match safety {
+        Some(safety) => format!("the function documents its safety contract as:\n{safety}"),
+        None => "this function does no unsafe operations and has no `# Safety` section \
+                 documenting why it is unsafe"
+            .to_string(),
+    }];
+    let unsafe_coercions = coercions.iter().filter(|(coerced, ty, _)| {
+        *coerced == did.to_def_id() && ty.fn_sig(tcx).unsafety() == hir::Unsafety::Unsafe
+    });
+    for (_, ty, span) in unsafe_coercions {
+        notes.push(format!(
+            "it is coerced to the `unsafe` fn pointer `{ty}` at {}, a safe function would \
+             coerce to it as well, so the `unsafe` can be dropped",
+            location(sm, *span)
+        ));
+    }
+    notes
+}
+
+/// A note on how many of the unsafe operations in `did` depend on its caller.
+fn dependency_note(
+    tcx: TyCtxt<'_>,
+    did: LocalDefId,
+    dependencies: &[Dependency],
+) -> Option<String> {
+    if dependencies.is_empty() {
+        return None;
+    }
+    let path = tcx.def_path_str(did.to_def_id());
+    let count = |kind| {
+        dependencies
+            .iter()
+            .filter(|dependency| **dependency == kind)
+            .count()
+    };
+    let (caller, global) = (count(Dependency::Caller), count(Dependency::Global));
+    Some(if caller == 0 && global == 0 {
+        format!(
+            "all unsafe operations only use locally constructed values, `{path}` could be made \
+             safe by wrapping them in an `unsafe {{}}` block"
+        )
+    } else if caller == 0 {
+        format!(
+            "{global} of {} unsafe operations use mutable or extern statics or call unsafe \
+             functions, check that their safety doesn't depend on the caller before making \
+             `{path}` safe",
+            dependencies.len()
+        )
+    } else {
+        format!(
+            "{caller} of {} unsafe operations depend on values from the caller, which is why \
+             `{path}` has to be unsafe",
+            dependencies.len()
+        )
+    })
+}
+
+/// Notes on the functions that may be behind each of the unsafe fn pointer types called.
+fn fn_pointer_notes<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    coercions: &[(DefId, Ty<'tcx>, Span)],
+    fn_pointer_types: &[Ty<'tcx>],
+) -> Vec<String> {
+    fn_pointer_types
+        .iter()
+        .map(|ty| {
+            let functions = provenance::coerced_functions(tcx, coercions, *ty);
+            if functions.is_empty() {
+                format!("no local functions are coerced to `{ty}`")
+            } else {
+                format!(
+                    "the functions coerced to `{ty}` are: {}",
+                    functions
+                        .iter()
+                        .map(|did| format!("`{}`", tcx.def_path_str(*did)))
+                        .join(", ")
+                )
+            }
+        })
+        .collect()
+}
+
+/// Notes on the safety contract of the called unsafe fn `callee`, and the impls a generic call
+/// to it can run.
+fn callee_notes(tcx: TyCtxt<'_>, callee: DefId) -> Vec<String> {
+    let path = tcx.def_path_str(callee);
+    let mut notes = vec![match docs::safety_docs(tcx, callee) {
+        Some(safety) => format!("`{path}` documents its safety contract as:\n{safety}"),
+        None => format!(
+            "`{path}` has no `# Safety` section, consult its documentation for information on \
+             how to avoid undefined behavior"
+        ),
+    }];
+    // Calls that were resolved to an impl record the impl's method instead.
+    let candidates = unsafety_visitor::candidate_impls(tcx, callee);
+    if !candidates.is_empty() {
+        notes.push(format!(
+            "the call to `{path}` is generic, it can run any of: {}",
+            candidates
+                .iter()
+                .map(|did| format!("`{}`", tcx.def_path_str(*did)))
+                .join(", ")
+        ));
+    }
+    notes
+}
+
 pub fn span_label<FileId>(
     id: FileId,
     sm: &rustc_span::source_map::SourceMap,
@@ -197,7 +452,7 @@ pub fn span_label<FileId>(
     }
 }
 pub struct FakeCallback {
-    selector: String,
+    pub selector: String,
 }
 
 impl FakeCallback {
@@ -210,15 +465,18 @@ impl FakeCallback {
         }
         let reasons = self.find_unsafe_things(tcx, fun_id)?;
 
-        let color: crate::opts::Coloring = std::env::var(crate::ENV_VAR_WHYNOT_COLORING)
-            .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?
-            .parse()
-            .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?;
+        let color = crate::coloring_from_env()?;
         SafeOutput {
             reasons,
             source_map: tcx.sess.source_map(),
         }
         .print(termcolor::StandardStream::stdout(color.into()), tcx, fun_id)?;
+        obligations::print(
+            tcx,
+            fun_id,
+            &obligations::collect(tcx, fun_id),
+            &mut std::io::stdout(),
+        )?;
         Ok(())
     }
 
@@ -246,11 +504,14 @@ impl FakeCallback {
                     //     "unsafe fn {:?} is a possible reason for unsafety",
                     //     violation
                     // );
-                    reasons.push(violation);
+                    reasons.push(violation.clone());
 
                     match violation.0 {
                         UnsafeOpKind::CallToUnsafeFunction(Some(new_did)) => {
-                            if let Some(new_did) = new_did.as_local() {
+                            // Trait methods that couldn't be resolved to an impl have no body.
+                            if let Some(new_did) = new_did.as_local()
+                                && tcx.hir().maybe_body_owned_by(new_did).is_some()
+                            {
                                 did = new_did;
                             } else {
                                 // A unlocal function is unsafe, so we can stop looking.
@@ -292,18 +553,20 @@ impl FakeCallback {
         for violation in &res {
             match violation.0 {
                 UnsafeOpKind::CallToUnsafeFunction(_) => {
-                    possible_reasons.push_back(*violation);
+                    possible_reasons.push_back(violation.clone());
                     unsafe_found = true;
                 }
                 _ => {
-                    reasons.push(*violation);
+                    reasons.push(violation.clone());
                     unsafe_found = true;
                 }
             }
         }
 
         if !unsafe_found {
-            reasons.push((UnsafeOpKind::ChoosenUnsafe, def_id, tcx.def_span(def_id)))
+            let (kind, span) = inherited_unsafety(tcx, def_id)
+                .unwrap_or((UnsafeOpKind::ChoosenUnsafe, tcx.def_span(def_id)));
+            reasons.push((kind, def_id, span))
         }
         Ok(())
     }
@@ -355,6 +618,21 @@ impl FakeCallback {
     }
 }
 
+/// Why `did` has to be unsafe to match a trait, even though its body does nothing unsafe.
+///
+/// Being coerced to an `unsafe` fn pointer is no reason, a safe fn coerces to it as well.
+pub fn inherited_unsafety(tcx: TyCtxt<'_>, did: LocalDefId) -> Option<(UnsafeOpKind, Span)> {
+    let trait_item = tcx.opt_associated_item(did.to_def_id())?.trait_item_def_id?;
+    if !unsafety_visitor::is_unsafe_fn(tcx, trait_item) {
+        return None;
+    }
+    // Only point at the trait if it's in this crate, its source may not be available.
+    let span = trait_item
+        .as_local()
+        .map_or_else(|| tcx.def_span(did), |trait_item| tcx.def_span(trait_item));
+    Some((UnsafeOpKind::InheritedFromInterface(trait_item), span))
+}
+
 impl rustc_driver::Callbacks for FakeCallback {
     fn config(&mut self, config: &mut rustc_interface::interface::Config) {
         config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
@@ -378,3 +656,26 @@ pub fn check_unsafety<'tcx>(
         .unwrap();
     std::process::exit(1);
 }
+
+/// Lists the unsafe fns in the crate that could be made safe.
+pub struct CandidatesCallback;
+
+impl rustc_driver::Callbacks for CandidatesCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_candidates);
+    }
+}
+
+pub fn check_candidates<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    candidates::find(tcx)
+        .and_then(|found| candidates::print(tcx, &found, &mut std::io::stdout()))
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
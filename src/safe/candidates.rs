@@ -0,0 +1,137 @@
+//! Finding unsafe fns across the crate that could be made safe.
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+};
+
+use eyre::Result;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::def_id::{DefId, LocalDefId};
+
+use super::{
+    dataflow::{self, Dependency},
+    unsafety_visitor::{self, UnsafeOpKind},
+    FakeCallback,
+};
+use crate::audit::inventory::location;
+
+/// Why an unsafe fn could be made safe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Why {
+    /// It does no unsafe operations.
+    ChoosenUnsafe,
+    /// All its unsafe operations only use values constructed in the fn.
+    LocallyJustified,
+}
+
+#[derive(Debug)]
+pub struct Candidate {
+    pub did: LocalDefId,
+    pub why: Why,
+    /// The fns calling it, which would no longer need `unsafe {}`.
+    pub callers: usize,
+    pub calls: usize,
+}
+
+/// All unsafe fns that could be made safe, the ones with the most callers first.
+pub fn find(tcx: TyCtxt<'_>) -> Result<Vec<Candidate>> {
+    let hir = tcx.hir();
+    let finder = FakeCallback {
+        selector: String::new(),
+    };
+
+    let mut calls: HashMap<DefId, (HashSet<LocalDefId>, usize)> = HashMap::new();
+    let mut unsafe_fns = vec![];
+    for did in hir.body_owners() {
+        if tcx.is_closure(did.to_def_id()) {
+            continue;
+        }
+        let violations =
+            unsafety_visitor::check_unsafety(tcx, unsafety_visitor::with_opt_const_param(tcx, did));
+        for (kind, _, _) in violations {
+            if let UnsafeOpKind::CallToUnsafeFunction(Some(callee)) = kind {
+                let (callers, count) = calls.entry(callee).or_default();
+                callers.insert(did);
+                *count += 1;
+            }
+        }
+        let is_unsafe_fn = unsafety_visitor::is_unsafe_fn(tcx, did.to_def_id());
+        // The signature of trait methods and their impls is decided by the trait.
+        let in_trait = tcx.trait_of_item(did.to_def_id()).is_some()
+            || tcx
+                .impl_of_method(did.to_def_id())
+                .and_then(|impl_| tcx.trait_id_of_impl(impl_))
+                .is_some();
+        if is_unsafe_fn && !in_trait {
+            unsafe_fns.push(did);
+        }
+    }
+
+    let mut candidates = vec![];
+    for did in unsafe_fns {
+        let reasons = finder.find_unsafe_things(tcx, did)?;
+        let own: Vec<_> = reasons
+            .iter()
+            .filter(|(_, owner, _)| *owner == did)
+            .collect();
+        let why = if own
+            .iter()
+            .all(|(kind, _, _)| matches!(kind, UnsafeOpKind::ChoosenUnsafe))
+        {
+            Why::ChoosenUnsafe
+        } else {
+            let flow = dataflow::classify(tcx, unsafety_visitor::with_opt_const_param(tcx, did));
+            if own
+                .iter()
+                .all(|(_, _, span)| flow.get(span) == Some(&Dependency::Local))
+            {
+                Why::LocallyJustified
+            } else {
+                continue;
+            }
+        };
+        let (callers, calls) = calls
+            .get(&did.to_def_id())
+            .map_or((0, 0), |(callers, calls)| (callers.len(), *calls));
+        candidates.push(Candidate {
+            did,
+            why,
+            callers,
+            calls,
+        });
+    }
+    candidates.sort_by_key(|candidate| {
+        (
+            std::cmp::Reverse(candidate.callers),
+            std::cmp::Reverse(candidate.calls),
+            tcx.def_path_str(candidate.did.to_def_id()),
+        )
+    });
+    Ok(candidates)
+}
+
+pub fn print(tcx: TyCtxt<'_>, candidates: &[Candidate], io: &mut impl Write) -> Result<()> {
+    if candidates.is_empty() {
+        writeln!(io, "no unsafe fns could be made safe")?;
+        return Ok(());
+    }
+    writeln!(
+        io,
+        "unsafe fns that could be made safe, by number of callers:"
+    )?;
+    for candidate in candidates {
+        writeln!(
+            io,
+            "  `{}` at {}: {}, {} callers ({} calls)",
+            tcx.def_path_str(candidate.did.to_def_id()),
+            location(tcx.sess.source_map(), tcx.def_span(candidate.did)),
+            match candidate.why {
+                Why::ChoosenUnsafe => "unsafe by choice",
+                Why::LocallyJustified => "all unsafe operations only use local values",
+            },
+            candidate.callers,
+            candidate.calls,
+        )?;
+    }
+    Ok(())
+}
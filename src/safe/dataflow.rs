@@ -0,0 +1,255 @@
+//! A simple dataflow pass over THIR, classifying whether the operands of each unsafe operation
+//! flow from the parameters of the function, from state outside of it, or are constructed locally.
+use std::collections::{HashMap, HashSet};
+
+use rustc_hir as hir;
+use rustc_middle::{
+    thir::{
+        visit::{self, Visitor},
+        *,
+    },
+    ty::{self, TyCtxt},
+};
+use rustc_span::{def_id::LocalDefId, Span};
+
+/// Where the operands of an unsafe operation come from, from least to most restrictive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Dependency {
+    /// The operands are only constructed locally, or are immutable statics.
+    Local,
+    /// The operation uses a mutable or extern static, or calls an unsafe fn, whose safety depends
+    /// on more than the function itself.
+    Global,
+    /// The operands depend on the parameters of the function, or variables captured by a closure.
+    Caller,
+}
+
+impl Dependency {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Dependency::Local => "only uses local values",
+            Dependency::Global => "depends on state outside the function",
+            Dependency::Caller => "depends on the caller",
+        }
+    }
+}
+
+/// Classify every expression in the body of `def` by its span.
+///
+/// The unsafe operations found by the unsafety visitor are reported with the span of their
+/// expression, so they can be looked up here. Operations in closures are not classified.
+pub fn classify(
+    tcx: TyCtxt<'_>,
+    def: ty::WithOptConstParam<LocalDefId>,
+) -> HashMap<Span, Dependency> {
+    let Ok((thir, expr)) = tcx.thir_body(def) else {
+        return HashMap::new();
+    };
+    let thir = &thir.borrow();
+    if thir.exprs.is_empty() {
+        return HashMap::new();
+    }
+
+    let mut tainted = HashSet::new();
+    for param in &thir.params {
+        if let Some(pat) = &param.pat {
+            bindings(thir, pat, &mut tainted);
+        }
+    }
+    // Propagate through `let`s, assignments and matches until nothing changes.
+    loop {
+        let mut propagate = Propagate {
+            thir,
+            tainted: &mut tainted,
+            changed: false,
+        };
+        propagate.visit_expr(&thir[expr]);
+        if !propagate.changed {
+            break;
+        }
+    }
+
+    let mut classify = Classify {
+        tcx,
+        thir,
+        tainted: &tainted,
+        frames: vec![],
+        dependencies: HashMap::new(),
+    };
+    classify.visit_expr(&thir[expr]);
+    classify.dependencies
+}
+
+/// Whether `expr` reads any tainted variable.
+fn depends<'tcx>(thir: &Thir<'tcx>, tainted: &HashSet<LocalVarId>, expr: &Expr<'tcx>) -> bool {
+    struct Reads<'a, 'tcx> {
+        thir: &'a Thir<'tcx>,
+        tainted: &'a HashSet<LocalVarId>,
+        found: bool,
+    }
+
+    impl<'a, 'tcx> Visitor<'a, 'tcx> for Reads<'a, 'tcx> {
+        fn thir(&self) -> &'a Thir<'tcx> {
+            self.thir
+        }
+
+        fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+            match expr.kind {
+                ExprKind::VarRef { id } => self.found |= self.tainted.contains(&id),
+                ExprKind::UpvarRef { .. } => self.found = true,
+                _ => visit::walk_expr(self, expr),
+            }
+        }
+    }
+
+    let mut reads = Reads {
+        thir,
+        tainted,
+        found: false,
+    };
+    reads.visit_expr(expr);
+    reads.found
+}
+
+/// Add the variables bound by `pat` to `vars`, returns whether any were new.
+fn bindings(thir: &Thir<'_>, pat: &Pat<'_>, vars: &mut HashSet<LocalVarId>) -> bool {
+    struct Bindings<'a, 'tcx> {
+        thir: &'a Thir<'tcx>,
+        vars: &'a mut HashSet<LocalVarId>,
+        changed: bool,
+    }
+
+    impl<'a, 'tcx> Visitor<'a, 'tcx> for Bindings<'a, 'tcx> {
+        fn thir(&self) -> &'a Thir<'tcx> {
+            self.thir
+        }
+
+        fn visit_pat(&mut self, pat: &Pat<'tcx>) {
+            if let PatKind::Binding { var, .. } = pat.kind {
+                self.changed |= self.vars.insert(var);
+            }
+            visit::walk_pat(self, pat);
+        }
+    }
+
+    let mut visitor = Bindings {
+        thir,
+        vars,
+        changed: false,
+    };
+    visitor.visit_pat(pat);
+    visitor.changed
+}
+
+/// The variable at the root of the place `expr`, e.g. `a` in `a.b[1]`.
+fn root_var(thir: &Thir<'_>, expr: ExprId) -> Option<LocalVarId> {
+    match thir[expr].kind {
+        ExprKind::VarRef { id } => Some(id),
+        ExprKind::Scope { value: place, .. }
+        | ExprKind::Field { lhs: place, .. }
+        | ExprKind::Index { lhs: place, .. } => root_var(thir, place),
+        _ => None,
+    }
+}
+
+struct Propagate<'a, 'tcx> {
+    thir: &'a Thir<'tcx>,
+    tainted: &'a mut HashSet<LocalVarId>,
+    changed: bool,
+}
+
+impl<'a, 'tcx> Visitor<'a, 'tcx> for Propagate<'a, 'tcx> {
+    fn thir(&self) -> &'a Thir<'tcx> {
+        self.thir
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt<'tcx>) {
+        if let StmtKind::Let {
+            pattern,
+            initializer: Some(init),
+            ..
+        } = &stmt.kind
+            && depends(self.thir, self.tainted, &self.thir[*init])
+        {
+            self.changed |= bindings(self.thir, pattern, self.tainted);
+        }
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+        match &expr.kind {
+            ExprKind::Assign { lhs, rhs } | ExprKind::AssignOp { lhs, rhs, .. } => {
+                if depends(self.thir, self.tainted, &self.thir[*rhs])
+                    && let Some(var) = root_var(self.thir, *lhs)
+                {
+                    self.changed |= self.tainted.insert(var);
+                }
+            }
+            ExprKind::Let { expr: scrutinee, pat } => {
+                if depends(self.thir, self.tainted, &self.thir[*scrutinee]) {
+                    self.changed |= bindings(self.thir, pat, self.tainted);
+                }
+            }
+            ExprKind::Match { scrutinee, arms } => {
+                if depends(self.thir, self.tainted, &self.thir[*scrutinee]) {
+                    for arm in arms.iter() {
+                        self.changed |= bindings(self.thir, &self.thir[*arm].pattern, self.tainted);
+                    }
+                }
+            }
+            _ => {}
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+/// Classifies each expression by the most restrictive dependency in it, in a single pass: each
+/// expression folds its own dependency into that of the expression it is in.
+struct Classify<'a, 'tcx> {
+    tcx: TyCtxt<'tcx>,
+    thir: &'a Thir<'tcx>,
+    tainted: &'a HashSet<LocalVarId>,
+    /// The dependencies of the expressions being visited, innermost last.
+    frames: Vec<Dependency>,
+    dependencies: HashMap<Span, Dependency>,
+}
+
+impl<'tcx> Classify<'_, 'tcx> {
+    /// The dependency of `expr` itself, without its subexpressions.
+    fn own(&self, expr: &Expr<'tcx>) -> Dependency {
+        match expr.kind {
+            ExprKind::VarRef { id } if self.tainted.contains(&id) => Dependency::Caller,
+            ExprKind::UpvarRef { .. } => Dependency::Caller,
+            ExprKind::StaticRef { def_id, .. } | ExprKind::ThreadLocalRef(def_id)
+                if self.tcx.is_mutable_static(def_id) || self.tcx.is_foreign_item(def_id) =>
+            {
+                Dependency::Global
+            }
+            ExprKind::Call { ty, .. }
+                if matches!(ty.kind(), ty::FnDef(..) | ty::FnPtr(..))
+                    && ty.fn_sig(self.tcx).unsafety() == hir::Unsafety::Unsafe =>
+            {
+                Dependency::Global
+            }
+            _ => Dependency::Local,
+        }
+    }
+}
+
+impl<'a, 'tcx> Visitor<'a, 'tcx> for Classify<'a, 'tcx> {
+    fn thir(&self) -> &'a Thir<'tcx> {
+        self.thir
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+        // The operands of the operation are all in the expression itself.
+        self.frames.push(self.own(expr));
+        visit::walk_expr(self, expr);
+        let dependency = self.frames.pop().unwrap();
+        if let Some(outer) = self.frames.last_mut() {
+            *outer = (*outer).max(dependency);
+        }
+        // Expressions wrapping another with the same span, like scopes, take precedence.
+        self.dependencies.insert(expr.span, dependency);
+    }
+}
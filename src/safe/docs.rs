@@ -0,0 +1,94 @@
+//! Reading the documentation of functions.
+use rustc_middle::ty::TyCtxt;
+use rustc_span::def_id::DefId;
+
+/// The doc comment of `did`, works for both local and external items.
+pub fn doc_comment(tcx: TyCtxt<'_>, did: DefId) -> String {
+    tcx.get_attrs_unchecked(did)
+        .iter()
+        .filter_map(|attr| attr.doc_str())
+        .flat_map(|doc| {
+            doc.as_str()
+                .lines()
+                .map(|line| line.strip_prefix(' ').unwrap_or(line).to_string())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The `# Safety` section of `did`'s documentation, or of the trait method it implements if it
+/// has none.
+pub fn safety_docs(tcx: TyCtxt<'_>, did: DefId) -> Option<String> {
+    safety_section(&doc_comment(tcx, did)).or_else(|| {
+        let trait_item = tcx.opt_associated_item(did)?.trait_item_def_id?;
+        safety_section(&doc_comment(tcx, trait_item))
+    })
+}
+
+/// The first paragraph of `did`'s documentation.
+pub fn summary(tcx: TyCtxt<'_>, did: DefId) -> Option<String> {
+    let docs = doc_comment(tcx, did);
+    let summary = docs.trim().split("\n\n").next()?.trim();
+    (!summary.is_empty()).then(|| summary.to_string())
+}
+
+/// Extract the contents of the `# Safety` section of a doc comment, without the heading.
+pub fn safety_section(docs: &str) -> Option<String> {
+    let mut level = None;
+    let mut section = vec![];
+    let mut in_code_block = false;
+    for line in docs.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") {
+            in_code_block = !in_code_block;
+        }
+        let heading = (!in_code_block && trimmed.starts_with('#'))
+            .then(|| {
+                let hashes = trimmed.chars().take_while(|c| *c == '#').count();
+                (hashes, trimmed[hashes..].trim())
+            })
+            .filter(|(_, title)| !title.is_empty());
+        match (level, heading) {
+            (None, Some((hashes, title))) if title.eq_ignore_ascii_case("safety") => {
+                level = Some(hashes);
+            }
+            (Some(level), Some((hashes, _))) if hashes <= level => break,
+            (Some(_), _) => section.push(line),
+            (None, _) => {}
+        }
+    }
+    level?;
+    let section = section.join("\n").trim().to_string();
+    (!section.is_empty()).then_some(section)
+}
+
+#[test]
+#[cfg(test)]
+fn test_safety_section() {
+    let docs = "\
+Reads the value.
+
+# Safety
+
+`ptr` must be valid for reads.
+
+```
+# let ptr = &1;
+unsafe { read(ptr) };
+```
+
+## Details
+
+Aligned too.
+
+# Examples
+
+Nothing here.";
+    assert_eq!(
+        safety_section(docs).as_deref(),
+        Some("`ptr` must be valid for reads.\n\n```\n# let ptr = &1;\nunsafe { read(ptr) };\n```\n\n## Details\n\nAligned too.")
+    );
+    assert_eq!(safety_section("# Safety\n\n# Panics"), None);
+    assert_eq!(safety_section("Does things.\n\n# Panics\n\nNever."), None);
+}
@@ -0,0 +1,239 @@
+//! Structured safety obligations of unsafe fns, and how they propagate to callers.
+//!
+//! An unsafe fn declares its obligations either with `#[whynot::requires(non_null(ptr), ..)]`
+//! (which needs `#![register_tool(whynot)]`), or as a list in its `# Safety` section where every
+//! item starts with the obligation in backticks and a colon:
+//!
+//! ```text
+//! # Safety
+//!
+//! - `non_null(ptr)`: `ptr` must not be null.
+//! - `aligned(ptr)`:
+//! ```
+//!
+//! Any other `# Safety` section is a single obligation, described by the whole section.
+use std::{collections::HashSet, io::Write};
+
+use eyre::Result;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{
+    def_id::{DefId, LocalDefId},
+    Span, Symbol,
+};
+
+use super::{
+    docs,
+    unsafety_visitor::{self, UnsafeOpKind},
+};
+use crate::audit::inventory::location;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Obligation {
+    /// The condition, e.g. `non_null(ptr)`.
+    pub condition: String,
+    pub description: Option<String>,
+    /// The function that declared the obligation.
+    pub origin: DefId,
+    /// The call through which the obligation was inherited, if it was.
+    pub call: Option<Span>,
+}
+
+/// The obligations `did` declares itself, or those of the trait method it implements if it
+/// declares none.
+pub fn declared(tcx: TyCtxt<'_>, did: DefId) -> Vec<Obligation> {
+    let path = [Symbol::intern("whynot"), Symbol::intern("requires")];
+    let sm = tcx.sess.source_map();
+    let mut conditions: Vec<(String, Option<String>)> = tcx
+        .get_attrs_unchecked(did)
+        .iter()
+        .filter(|attr| attr.path_matches(&path))
+        .flat_map(|attr| attr.meta_item_list().unwrap_or_default())
+        .map(|item| {
+            let condition = sm
+                .span_to_snippet(item.span())
+                .unwrap_or_else(|_| item.name_or_empty().to_string());
+            (condition, None)
+        })
+        .collect();
+    if let Some(safety) = docs::safety_section(&docs::doc_comment(tcx, did)) {
+        let tagged = tagged_list(&safety);
+        if tagged.is_empty() {
+            let section = safety.split_whitespace().collect::<Vec<_>>().join(" ");
+            conditions.push(("safety".to_string(), Some(section)));
+        }
+        conditions.extend(tagged);
+    }
+    if conditions.is_empty()
+        && let Some(trait_item) = tcx
+            .opt_associated_item(did)
+            .and_then(|item| item.trait_item_def_id)
+    {
+        return declared(tcx, trait_item);
+    }
+    conditions
+        .into_iter()
+        .map(|(condition, description)| Obligation {
+            condition,
+            description,
+            origin: did,
+            call: None,
+        })
+        .collect()
+}
+
+/// The obligations callers of `did` must uphold: its own, and those of every unsafe fn it calls
+/// outside of an `unsafe {}` block. A call in an unsafe block discharges the callee's
+/// obligations.
+pub fn collect(tcx: TyCtxt<'_>, did: LocalDefId) -> Vec<Obligation> {
+    let mut obligations = vec![];
+    collect_(tcx, did, &mut HashSet::new(), &mut obligations);
+    obligations
+}
+
+fn collect_(
+    tcx: TyCtxt<'_>,
+    did: LocalDefId,
+    visited: &mut HashSet<LocalDefId>,
+    obligations: &mut Vec<Obligation>,
+) {
+    if !visited.insert(did) {
+        return;
+    }
+    let mut push = |obligation: Obligation| {
+        if !obligations
+            .iter()
+            .any(|o| o.condition == obligation.condition && o.origin == obligation.origin)
+        {
+            obligations.push(obligation);
+        }
+    };
+    for obligation in declared(tcx, did.to_def_id()) {
+        push(obligation);
+    }
+    let report = unsafety_visitor::check_body_unsafety(
+        tcx,
+        unsafety_visitor::with_opt_const_param(tcx, did),
+    );
+    for (kind, _, span) in &report.violations {
+        let UnsafeOpKind::CallToUnsafeFunction(Some(callee)) = kind else {
+            continue;
+        };
+        if report
+            .unsafe_blocks
+            .iter()
+            .any(|block| block.span.contains(*span))
+        {
+            continue;
+        }
+        let inherited = match callee.as_local() {
+            Some(local) if unsafety_visitor::is_unsafe_fn(tcx, local.to_def_id()) => {
+                let mut inherited = vec![];
+                collect_(tcx, local, visited, &mut inherited);
+                inherited
+            }
+            _ => declared(tcx, *callee),
+        };
+        for obligation in inherited {
+            // Keep the call site in `did` of the outermost call.
+            push(Obligation {
+                call: Some(*span),
+                ..obligation
+            });
+        }
+    }
+}
+
+/// Print the obligations callers of `did` must uphold, and where each one came from.
+pub fn print(
+    tcx: TyCtxt<'_>,
+    did: LocalDefId,
+    obligations: &[Obligation],
+    io: &mut impl Write,
+) -> Result<()> {
+    if obligations.is_empty() {
+        return Ok(());
+    }
+    writeln!(
+        io,
+        "callers of `{}` must uphold:",
+        tcx.def_path_str(did.to_def_id())
+    )?;
+    for obligation in obligations {
+        write!(io, "  - {}", obligation.condition)?;
+        if let Some(description) = &obligation.description {
+            write!(io, ": {description}")?;
+        }
+        let origin = tcx.def_path_str(obligation.origin);
+        match obligation.call {
+            Some(call) => writeln!(
+                io,
+                " (from `{origin}`, called at {})",
+                location(tcx.sess.source_map(), call)
+            )?,
+            None => writeln!(io, " (declared by `{origin}`)")?,
+        }
+    }
+    Ok(())
+}
+
+/// The list items in `section` that start with a condition in backticks, followed by a colon.
+///
+/// Items like ``* `src` must be valid`` only mention an argument, they don't declare a condition.
+fn tagged_list(section: &str) -> Vec<(String, Option<String>)> {
+    section
+        .lines()
+        .filter_map(|line| {
+            let item = line
+                .trim_start()
+                .strip_prefix("- ")
+                .or_else(|| line.trim_start().strip_prefix("* "))?;
+            let (condition, rest) = item.strip_prefix('`')?.split_once('`')?;
+            let description = rest.strip_prefix(':')?.trim();
+            Some((
+                condition.to_string(),
+                (!description.is_empty()).then(|| description.to_string()),
+            ))
+        })
+        .collect()
+}
+
+#[test]
+#[cfg(test)]
+fn test_tagged_list() {
+    let section = "\
+The pointer must be usable:
+
+- `non_null(ptr)`: `ptr` must not be null.
+* `aligned(ptr)`:
+- it must also be valid, but this is not tagged.
+- `ptr` must point to an initialized value, without a colon this is not a condition.";
+    assert_eq!(
+        tagged_list(section),
+        vec![
+            (
+                "non_null(ptr)".to_string(),
+                Some("`ptr` must not be null.".to_string())
+            ),
+            ("aligned(ptr)".to_string(), None),
+        ]
+    );
+}
+
+#[test]
+#[cfg(test)]
+fn test_tagged_list_std() {
+    // From `std::ptr::copy_nonoverlapping`.
+    let section = "\
+Behavior is undefined if any of the following conditions are violated:
+
+* `src` must be [valid] for reads of `count * size_of::<T>()` bytes.
+
+* `dst` must be [valid] for writes of `count * size_of::<T>()` bytes.
+
+* Both `src` and `dst` must be properly aligned.
+
+* The region of memory beginning at `src` with a size of `count *
+  size_of::<T>()` bytes must *not* overlap with the region of memory
+  beginning at `dst` with the same size.";
+    assert!(tagged_list(section).is_empty());
+}
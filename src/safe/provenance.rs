@@ -0,0 +1,408 @@
+//! Tracing where the raw pointers that are dereferenced, and the unsafe fn pointers that are
+//! called, come from.
+use std::collections::{HashMap, HashSet};
+
+use rustc_hir as hir;
+use rustc_middle::{
+    thir::{
+        visit::{self, Visitor},
+        *,
+    },
+    ty::{self, Ty, TyCtxt},
+};
+use rustc_span::{
+    def_id::{DefId, LocalDefId},
+    Span,
+};
+
+/// Where a pointer came from, with the span of the expression it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Provenance {
+    /// Cast or coerced from a reference.
+    Reference(hir::Mutability, Span),
+    /// `addr_of!` or `addr_of_mut!`.
+    AddrOf(hir::Mutability, Span),
+    /// A function like `Box::into_raw`, giving up ownership.
+    IntoRaw(DefId, Span),
+    /// Returned by a foreign function.
+    Ffi(DefId, Span),
+    /// Returned by any other function.
+    Call(DefId, Span),
+    /// Cast from an integer.
+    Integer(Span),
+    /// Pointer arithmetic, like `add` or `offset`, on another pointer.
+    Arithmetic(DefId, Span, Box<Provenance>),
+    /// A parameter of the function.
+    Parameter(Span),
+    /// A function item coerced to a fn pointer.
+    Function(DefId, Span),
+    /// A field of a struct, union or tuple.
+    Field(String, Span),
+    /// A `static`.
+    Static(DefId, Span),
+    /// An element of an array or slice, like a table of fn pointers.
+    Index(Span, Option<Box<Provenance>>),
+}
+
+impl Provenance {
+    /// The span of the expression, at its call site if it comes from a macro like `addr_of!`.
+    pub fn span(&self) -> Span {
+        let span = match self {
+            Provenance::Reference(_, span)
+            | Provenance::AddrOf(_, span)
+            | Provenance::IntoRaw(_, span)
+            | Provenance::Ffi(_, span)
+            | Provenance::Call(_, span)
+            | Provenance::Integer(span)
+            | Provenance::Arithmetic(_, span, _)
+            | Provenance::Parameter(span)
+            | Provenance::Function(_, span)
+            | Provenance::Field(_, span)
+            | Provenance::Static(_, span)
+            | Provenance::Index(span, _) => *span,
+        };
+        span.source_callsite()
+    }
+
+    pub fn description(&self, tcx: TyCtxt<'_>) -> String {
+        match self {
+            Provenance::Reference(hir::Mutability::Mut, _) => "a `&mut` reference".to_string(),
+            Provenance::Reference(hir::Mutability::Not, _) => "a `&` reference".to_string(),
+            Provenance::AddrOf(hir::Mutability::Mut, _) => "`addr_of_mut!`".to_string(),
+            Provenance::AddrOf(hir::Mutability::Not, _) => "`addr_of!`".to_string(),
+            Provenance::IntoRaw(did, _) => format!("`{}`", tcx.def_path_str(*did)),
+            Provenance::Ffi(did, _) => format!(
+                "the return value of the foreign function `{}`",
+                tcx.def_path_str(*did)
+            ),
+            Provenance::Call(did, _) => format!("the return value of `{}`", tcx.def_path_str(*did)),
+            Provenance::Integer(_) => "an integer cast".to_string(),
+            Provenance::Arithmetic(did, _, base) => format!(
+                "pointer arithmetic with `{}` on {}",
+                tcx.item_name(*did),
+                base.description(tcx)
+            ),
+            Provenance::Parameter(_) => "a parameter of the function".to_string(),
+            Provenance::Function(did, _) => format!("the function `{}`", tcx.def_path_str(*did)),
+            Provenance::Field(name, _) => format!("the field `{name}`"),
+            Provenance::Static(did, _) => format!("the static `{}`", tcx.def_path_str(*did)),
+            Provenance::Index(_, None) => "an element of an array or slice".to_string(),
+            Provenance::Index(_, Some(base)) => {
+                format!("an element of an array or slice in {}", base.description(tcx))
+            }
+        }
+    }
+}
+
+/// The provenance of the pointer in every dereference of a raw pointer in the body of `def`, by
+/// the span of the dereference.
+pub fn trace_derefs(
+    tcx: TyCtxt<'_>,
+    def: ty::WithOptConstParam<LocalDefId>,
+) -> HashMap<Span, Provenance> {
+    trace(tcx, def, |thir, expr| match expr.kind {
+        ExprKind::Deref { arg } if thir[arg].ty.is_unsafe_ptr() => Some(arg),
+        _ => None,
+    })
+    .into_iter()
+    .filter_map(|(span, (provenance, _))| Some((span, provenance?)))
+    .collect()
+}
+
+/// The provenance and type of the fn pointer in every call through a fn pointer in the body of
+/// `def`, by the span of the call.
+pub fn trace_fn_pointer_calls<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    def: ty::WithOptConstParam<LocalDefId>,
+) -> HashMap<Span, (Option<Provenance>, Ty<'tcx>)> {
+    trace(tcx, def, |thir, expr| match expr.kind {
+        ExprKind::Call { fun, .. } if thir[fun].ty.is_fn_ptr() => Some(fun),
+        _ => None,
+    })
+}
+
+/// Trace the provenance of the expression `select` picks out of an expression, for every
+/// expression it picks one out of.
+fn trace<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    def: ty::WithOptConstParam<LocalDefId>,
+    select: fn(&Thir<'tcx>, &Expr<'tcx>) -> Option<ExprId>,
+) -> HashMap<Span, (Option<Provenance>, Ty<'tcx>)> {
+    let Ok((thir, expr)) = tcx.thir_body(def) else {
+        return HashMap::new();
+    };
+    let thir = &thir.borrow();
+    if thir.exprs.is_empty() {
+        return HashMap::new();
+    }
+
+    let mut definitions = Definitions {
+        thir,
+        lets: HashMap::new(),
+        params: HashMap::new(),
+        assigned: HashSet::new(),
+    };
+    for param in &thir.params {
+        if let Some(pat) = &param.pat
+            && let Some(var) = binding(pat)
+        {
+            definitions.params.insert(var, pat.span);
+        }
+    }
+    definitions.visit_expr(&thir[expr]);
+
+    let mut tracer = Tracer {
+        tcx,
+        definitions: &definitions,
+        select,
+        provenances: HashMap::new(),
+    };
+    tracer.visit_expr(&thir[expr]);
+    tracer.provenances
+}
+
+/// The local functions that are coerced to the fn pointer type `fn_ptr`, out of all
+/// `coercions` in the crate found by [`fn_coercions`].
+pub fn coerced_functions<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    coercions: &[(DefId, Ty<'tcx>, Span)],
+    fn_ptr: Ty<'tcx>,
+) -> Vec<DefId> {
+    let fn_ptr = tcx.erase_regions(fn_ptr);
+    let mut functions = vec![];
+    for &(did, ty, _) in coercions {
+        if ty == fn_ptr && !functions.contains(&did) {
+            functions.push(did);
+        }
+    }
+    functions
+}
+
+/// Every coercion of a local function to a fn pointer in the crate, with the type of the fn
+/// pointer (with regions erased) and the span of the coerced expression.
+pub fn fn_coercions(tcx: TyCtxt<'_>) -> Vec<(DefId, Ty<'_>, Span)> {
+    struct Coercions<'a, 'tcx> {
+        tcx: TyCtxt<'tcx>,
+        thir: &'a Thir<'tcx>,
+        coercions: &'a mut Vec<(DefId, Ty<'tcx>, Span)>,
+    }
+
+    impl<'a, 'tcx> Visitor<'a, 'tcx> for Coercions<'a, 'tcx> {
+        fn thir(&self) -> &'a Thir<'tcx> {
+            self.thir
+        }
+
+        fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+            if let ExprKind::Pointer { source, .. } = expr.kind {
+                // A safe fn item is first reified to a safe fn pointer, and then cast to an
+                // unsafe one.
+                let mut source = &self.thir[source];
+                while let ExprKind::Pointer { source: inner, .. }
+                | ExprKind::Scope { value: inner, .. }
+                | ExprKind::Use { source: inner } = source.kind
+                {
+                    source = &self.thir[inner];
+                }
+                if let &ty::FnDef(did, _) = source.ty.kind()
+                    && did.is_local()
+                {
+                    self.coercions
+                        .push((did, self.tcx.erase_regions(expr.ty), expr.span));
+                    return;
+                }
+            }
+            visit::walk_expr(self, expr);
+        }
+    }
+
+    let mut coercions = vec![];
+    for did in tcx.hir().body_owners() {
+        let def = super::unsafety_visitor::with_opt_const_param(tcx, did);
+        let Ok((thir, expr)) = tcx.thir_body(def) else {
+            continue;
+        };
+        let thir = &thir.borrow();
+        if thir.exprs.is_empty() {
+            continue;
+        }
+        Coercions {
+            tcx,
+            thir,
+            coercions: &mut coercions,
+        }
+        .visit_expr(&thir[expr]);
+    }
+    coercions
+}
+
+/// The variable bound by a pattern that binds nothing else.
+fn binding(pat: &Pat<'_>) -> Option<LocalVarId> {
+    match &pat.kind {
+        PatKind::Binding {
+            var,
+            subpattern: None,
+            ..
+        } => Some(*var),
+        PatKind::AscribeUserType { subpattern, .. } => binding(subpattern),
+        _ => None,
+    }
+}
+
+/// Where each variable is defined.
+struct Definitions<'a, 'tcx> {
+    thir: &'a Thir<'tcx>,
+    lets: HashMap<LocalVarId, ExprId>,
+    params: HashMap<LocalVarId, Span>,
+    /// Variables assigned to after their definition, their provenance is not tracked.
+    assigned: HashSet<LocalVarId>,
+}
+
+impl<'a, 'tcx> Visitor<'a, 'tcx> for Definitions<'a, 'tcx> {
+    fn thir(&self) -> &'a Thir<'tcx> {
+        self.thir
+    }
+
+    fn visit_stmt(&mut self, stmt: &Stmt<'tcx>) {
+        if let StmtKind::Let {
+            pattern,
+            initializer: Some(init),
+            ..
+        } = &stmt.kind
+            && let Some(var) = binding(pattern)
+        {
+            self.lets.insert(var, *init);
+        }
+        visit::walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+        if let ExprKind::Assign { lhs, .. } | ExprKind::AssignOp { lhs, .. } = expr.kind
+            && let ExprKind::VarRef { id } = self.thir[lhs].kind
+        {
+            self.assigned.insert(id);
+        }
+        visit::walk_expr(self, expr);
+    }
+}
+
+struct Tracer<'a, 'tcx> {
+    tcx: TyCtxt<'tcx>,
+    definitions: &'a Definitions<'a, 'tcx>,
+    select: fn(&Thir<'tcx>, &Expr<'tcx>) -> Option<ExprId>,
+    provenances: HashMap<Span, (Option<Provenance>, Ty<'tcx>)>,
+}
+
+impl<'a, 'tcx> Tracer<'a, 'tcx> {
+    fn trace(&self, expr: ExprId, depth: usize) -> Option<Provenance> {
+        let thir = self.definitions.thir;
+        if depth == 0 {
+            return None;
+        }
+        let expr = &thir[expr];
+        if let &ty::FnDef(did, _) = expr.ty.kind() {
+            return Some(Provenance::Function(did, expr.span));
+        }
+        match expr.kind {
+            ExprKind::Scope { value: source, .. }
+            | ExprKind::Use { source }
+            | ExprKind::Pointer { source, .. } => self.trace(source, depth - 1),
+            ExprKind::Cast { source } if thir[source].ty.is_integral() => {
+                Some(Provenance::Integer(expr.span))
+            }
+            ExprKind::Cast { source } => self.trace(source, depth - 1),
+            ExprKind::AddressOf { mutability, arg } => match thir[arg].kind {
+                // A reference coerced to a pointer is a reborrow of the reference.
+                ExprKind::Deref { arg: reference } if thir[reference].ty.is_ref() => {
+                    Some(Provenance::Reference(mutability, expr.span))
+                }
+                _ => Some(Provenance::AddrOf(mutability, expr.span)),
+            },
+            ExprKind::VarRef { id } => {
+                if let Some(span) = self.definitions.params.get(&id) {
+                    Some(Provenance::Parameter(*span))
+                } else if self.definitions.assigned.contains(&id) {
+                    None
+                } else {
+                    self.trace(*self.definitions.lets.get(&id)?, depth - 1)
+                }
+            }
+            ExprKind::Deref { arg } => match thir[arg].kind {
+                ExprKind::StaticRef { def_id, .. } => Some(Provenance::Static(def_id, expr.span)),
+                _ => None,
+            },
+            ExprKind::Field {
+                lhs,
+                variant_index,
+                name,
+            } => {
+                let name = match thir[lhs].ty.kind() {
+                    ty::Adt(adt_def, _) => adt_def.variant(variant_index).fields[name.index()]
+                        .name
+                        .to_string(),
+                    _ => name.index().to_string(),
+                };
+                Some(Provenance::Field(name, expr.span))
+            }
+            ExprKind::Index { lhs, .. } => Some(Provenance::Index(
+                expr.span,
+                self.trace(lhs, depth - 1).map(Box::new),
+            )),
+            ExprKind::Call { fun, ref args, .. } => {
+                let &ty::FnDef(did, _) = thir[fun].ty.kind() else {
+                    return None;
+                };
+                let name = self.tcx.item_name(did);
+                if name.as_str() == "transmute"
+                    && let Some(&arg) = args.first()
+                    && let Some(provenance) = self.trace(arg, depth - 1)
+                {
+                    // Like the result of `dlsym` transmuted to a fn pointer.
+                    Some(provenance)
+                } else if self.tcx.is_foreign_item(did) {
+                    Some(Provenance::Ffi(did, expr.span))
+                } else if name.as_str() == "into_raw" {
+                    Some(Provenance::IntoRaw(did, expr.span))
+                } else if is_arithmetic(name.as_str())
+                    && let Some(&base) = args.first()
+                    && thir[base].ty.is_unsafe_ptr()
+                {
+                    let base = self.trace(base, depth - 1)?;
+                    Some(Provenance::Arithmetic(did, expr.span, Box::new(base)))
+                } else {
+                    Some(Provenance::Call(did, expr.span))
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+fn is_arithmetic(method: &str) -> bool {
+    matches!(
+        method,
+        "add"
+            | "sub"
+            | "offset"
+            | "wrapping_add"
+            | "wrapping_sub"
+            | "wrapping_offset"
+            | "byte_add"
+            | "byte_sub"
+            | "byte_offset"
+    )
+}
+
+impl<'a, 'tcx> Visitor<'a, 'tcx> for Tracer<'a, 'tcx> {
+    fn thir(&self) -> &'a Thir<'tcx> {
+        self.definitions.thir
+    }
+
+    fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+        if let Some(pointer) = (self.select)(self.definitions.thir, expr) {
+            let provenance = self.trace(pointer, 16);
+            let ty = self.definitions.thir[pointer].ty;
+            self.provenances.insert(expr.span, (provenance, ty));
+        }
+        visit::walk_expr(self, expr);
+    }
+}
@@ -0,0 +1,95 @@
+//! Explaining calls to functions with `#[target_feature]`.
+use itertools::Itertools;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{
+    def_id::{DefId, LocalDefId},
+    Symbol,
+};
+
+/// Notes on a call from `caller` to `callee`, where `missing` are the features `callee` enables
+/// that `caller` doesn't, as recorded in `UnsafeOpKind::CallToFunctionWith`.
+pub fn explain(
+    tcx: TyCtxt<'_>,
+    caller: LocalDefId,
+    callee: DefId,
+    missing: &[Symbol],
+) -> Vec<String> {
+    let caller_path = tcx.def_path_str(caller.to_def_id());
+    let callee_path = tcx.def_path_str(callee);
+    let features = tcx
+        .codegen_fn_attrs(callee)
+        .target_features
+        .iter()
+        .map(|feature| format!("`{feature}`"))
+        .join(", ");
+    let mut notes = vec![format!(
+        "`{callee_path}` enables {features}, `{caller_path}` doesn't enable {}",
+        quoted(missing)
+    )];
+
+    let by_default: Vec<_> = missing
+        .iter()
+        .copied()
+        .filter(|feature| tcx.sess.target_features.contains(feature))
+        .collect();
+    if !by_default.is_empty() {
+        notes.push(format!(
+            "{} enabled by default for the target `{}`, but the call is unsafe until \
+             `{caller_path}` enables them with `#[target_feature]` too",
+            if by_default.len() == 1 {
+                format!("{} is", quoted(&by_default))
+            } else {
+                format!("{} are", quoted(&by_default))
+            },
+            tcx.sess.opts.target_triple
+        ));
+    }
+
+    let attribute = missing
+        .iter()
+        .map(|feature| format!("enable = \"{feature}\""))
+        .join(", ");
+    notes.push(format!(
+        "adding `#[target_feature({attribute})]` to `{caller_path}` removes the need for \
+         `unsafe` here, but makes `{caller_path}` unsafe to call instead"
+    ));
+    if let Some((detect, stable)) = detection_macro(&tcx.sess.target.arch) {
+        let checks = missing
+            .iter()
+            .map(|feature| format!("{detect}!(\"{feature}\")"))
+            .join(" && ");
+        notes.push(format!(
+            "to keep `{caller_path}` safe, only make the call after checking `{checks}` at \
+             runtime, and justify the `unsafe` block with it"
+        ));
+        if !stable {
+            notes.push(format!(
+                "`{detect}!` is unstable, it needs a nightly compiler and \
+                 `#![feature(stdsimd)]`"
+            ));
+        }
+    }
+    notes
+}
+
+fn quoted(features: &[Symbol]) -> String {
+    features
+        .iter()
+        .map(|feature| format!("`{feature}`"))
+        .join(", ")
+}
+
+/// The `std` macro detecting target features at runtime on `arch`, and whether it is stable.
+fn detection_macro(arch: &str) -> Option<(&'static str, bool)> {
+    match arch {
+        "x86" | "x86_64" => Some(("is_x86_feature_detected", true)),
+        "aarch64" => Some(("is_aarch64_feature_detected", true)),
+        "arm" => Some(("is_arm_feature_detected", false)),
+        "riscv32" | "riscv64" => Some(("is_riscv_feature_detected", false)),
+        "powerpc" => Some(("is_powerpc_feature_detected", false)),
+        "powerpc64" => Some(("is_powerpc64_feature_detected", false)),
+        "mips" => Some(("is_mips_feature_detected", false)),
+        "mips64" => Some(("is_mips64_feature_detected", false)),
+        _ => None,
+    }
+}
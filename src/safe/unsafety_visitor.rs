@@ -5,13 +5,14 @@ use rustc_errors::struct_span_err;
 use rustc_hir as hir;
 use rustc_middle::mir::BorrowKind;
 use rustc_middle::thir::*;
-use rustc_middle::ty::{self, ParamEnv, Ty, TyCtxt};
+use rustc_middle::ty::{self, subst::SubstsRef, ParamEnv, Ty, TyCtxt};
 use rustc_session::lint::builtin::{UNSAFE_OP_IN_UNSAFE_FN, UNUSED_UNSAFE};
 use rustc_session::lint::Level;
 use rustc_span::def_id::{DefId, LocalDefId};
 use rustc_span::symbol::Symbol;
 use rustc_span::Span;
 
+use itertools::Itertools;
 use std::borrow::Cow;
 use std::ops::Bound;
 use std::sync::{Arc, Mutex};
@@ -32,10 +33,15 @@ struct UnsafetyVisitor<'a, 'tcx> {
     /// When inside the LHS of an assignment to a field, this is the type
     /// of the LHS and the span of the assignment expression.
     assignment_info: Option<(Ty<'tcx>, Span)>,
-    in_union_destructure: bool,
+    /// The union and field being destructured by the current pattern, if any.
+    in_union_destructure: Option<(DefId, Option<Symbol>)>,
+    /// How the place being visited is used, for accesses to union fields.
+    union_access: UnionAccess,
     param_env: ParamEnv<'tcx>,
-    inside_adt: bool,
+    /// The layout constrained type whose pattern we are in, if any.
+    inside_adt: Option<DefId>,
     violations: Arc<Mutex<Vec<(UnsafeOpKind, LocalDefId, Span)>>>,
+    unsafe_blocks: Arc<Mutex<Vec<UnsafeBlock>>>,
     current_did: LocalDefId,
 }
 
@@ -63,6 +69,14 @@ impl<'tcx> UnsafetyVisitor<'_, 'tcx> {
             );
             f(self);
         } else {
+            if let SafetyContext::UnsafeBlock { span, hir_id, .. } = safety_context {
+                self.unsafe_blocks.lock().unwrap().push(UnsafeBlock {
+                    hir_id,
+                    span,
+                    owner: self.current_did,
+                    ops: vec![],
+                });
+            }
             let prev_context = self.safety_context;
             self.safety_context = safety_context;
 
@@ -96,10 +110,24 @@ impl<'tcx> UnsafetyVisitor<'_, 'tcx> {
         self.violations
             .lock()
             .unwrap()
-            .push((kind, self.current_did, span));
+            .push((kind.clone(), self.current_did, span));
         match self.safety_context {
             SafetyContext::BuiltinUnsafeBlock => {}
-            SafetyContext::UnsafeBlock { ref mut used, .. } => {
+            SafetyContext::UnsafeBlock {
+                ref mut used,
+                hir_id,
+                ..
+            } => {
+                if let Some(block) = self
+                    .unsafe_blocks
+                    .lock()
+                    .unwrap()
+                    .iter_mut()
+                    .rev()
+                    .find(|block| block.hir_id == hir_id)
+                {
+                    block.ops.push((kind.clone(), span));
+                }
                 // Mark this block as useful (even inside `unsafe fn`, where it is technically
                 // redundant -- but we want to eventually enable `unsafe_op_in_unsafe_fn` by
                 // default which will require those blocks:
@@ -175,7 +203,8 @@ impl<'tcx> UnsafetyVisitor<'_, 'tcx> {
 
 // Searches for accesses to layout constrained fields.
 struct LayoutConstrainedPlaceVisitor<'a, 'tcx> {
-    found: bool,
+    /// The layout constrained type found.
+    found: Option<DefId>,
     thir: &'a Thir<'tcx>,
     tcx: TyCtxt<'tcx>,
 }
@@ -183,7 +212,7 @@ struct LayoutConstrainedPlaceVisitor<'a, 'tcx> {
 impl<'a, 'tcx> LayoutConstrainedPlaceVisitor<'a, 'tcx> {
     fn new(thir: &'a Thir<'tcx>, tcx: TyCtxt<'tcx>) -> Self {
         Self {
-            found: false,
+            found: None,
             thir,
             tcx,
         }
@@ -202,7 +231,7 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for LayoutConstrainedPlaceVisitor<'a, 'tcx> {
                     if (Bound::Unbounded, Bound::Unbounded)
                         != self.tcx.layout_scalar_valid_range(adt_def.did())
                     {
-                        self.found = true;
+                        self.found = Some(adt_def.did());
                     }
                 }
                 visit::walk_expr(self, expr);
@@ -252,7 +281,7 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
     }
 
     fn visit_pat(&mut self, pat: &Pat<'tcx>) {
-        if self.in_union_destructure {
+        if let Some((union, field)) = self.in_union_destructure {
             match pat.kind {
                 // binding to a variable allows getting stuff out of variable
                 PatKind::Binding { .. }
@@ -264,7 +293,10 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                 | PatKind::Range { .. }
                 | PatKind::Slice { .. }
                 | PatKind::Array { .. } => {
-                    self.requires_unsafe(pat.span, AccessToUnionField);
+                    self.requires_unsafe(
+                        pat.span,
+                        AccessToUnionField(union, field, UnionAccess::Pattern),
+                    );
                     return; // we can return here since this already requires unsafe
                 }
                 // wildcard doesn't take anything
@@ -276,17 +308,24 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
         };
 
         match &pat.kind {
-            PatKind::Leaf { .. } => {
+            PatKind::Leaf { subpatterns } => {
                 if let ty::Adt(adt_def, ..) = pat.ty.kind() {
                     if adt_def.is_union() {
-                        let old_in_union_destructure =
-                            std::mem::replace(&mut self.in_union_destructure, true);
-                        visit::walk_pat(self, pat);
-                        self.in_union_destructure = old_in_union_destructure;
+                        let fields = &adt_def.non_enum_variant().fields;
+                        for subpattern in subpatterns {
+                            let field = fields[subpattern.field.index()].name;
+                            let old_in_union_destructure = std::mem::replace(
+                                &mut self.in_union_destructure,
+                                Some((adt_def.did(), Some(field))),
+                            );
+                            self.visit_pat(&subpattern.pattern);
+                            self.in_union_destructure = old_in_union_destructure;
+                        }
                     } else if (Bound::Unbounded, Bound::Unbounded)
                         != self.tcx.layout_scalar_valid_range(adt_def.did())
                     {
-                        let old_inside_adt = std::mem::replace(&mut self.inside_adt, true);
+                        let old_inside_adt =
+                            std::mem::replace(&mut self.inside_adt, Some(adt_def.did()));
                         visit::walk_pat(self, pat);
                         self.inside_adt = old_inside_adt;
                     } else {
@@ -301,7 +340,7 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                 ty,
                 ..
             } => {
-                if self.inside_adt {
+                if let Some(adt) = self.inside_adt {
                     let ty::Ref(_, ty, _) = ty.kind() else {
                         // pat.span,
                         unimplemented!(
@@ -312,18 +351,21 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                     match borrow_kind {
                         BorrowKind::Shallow | BorrowKind::Shared | BorrowKind::Unique => {
                             if !ty.is_freeze(self.tcx.at(pat.span), self.param_env) {
-                                self.requires_unsafe(pat.span, BorrowOfLayoutConstrainedField);
+                                self.requires_unsafe(
+                                    pat.span,
+                                    BorrowOfLayoutConstrainedField(adt),
+                                );
                             }
                         }
                         BorrowKind::Mut { .. } => {
-                            self.requires_unsafe(pat.span, MutationOfLayoutConstrainedField);
+                            self.requires_unsafe(pat.span, MutationOfLayoutConstrainedField(adt));
                         }
                     }
                 }
                 visit::walk_pat(self, pat);
             }
             PatKind::Deref { .. } => {
-                let old_inside_adt = std::mem::replace(&mut self.inside_adt, false);
+                let old_inside_adt = std::mem::replace(&mut self.inside_adt, None);
                 visit::walk_pat(self, pat);
                 self.inside_adt = old_inside_adt;
             }
@@ -386,6 +428,7 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                 // because all the place expressions can't have more
                 // than one child.
                 self.assignment_info = None;
+                self.union_access = UnionAccess::Read;
             }
         };
 
@@ -410,8 +453,8 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                 fn_span: _,
             } => {
                 if self.thir[fun].ty.fn_sig(self.tcx).unsafety() == hir::Unsafety::Unsafe {
-                    let func_id = if let ty::FnDef(func_id, _) = self.thir[fun].ty.kind() {
-                        Some(*func_id)
+                    let func_id = if let ty::FnDef(func_id, substs) = self.thir[fun].ty.kind() {
+                        Some(resolve_callee(self.tcx, self.param_env, *func_id, substs))
                     } else {
                         None
                     };
@@ -421,15 +464,16 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                     // the call requires `unsafe`. Don't check this on wasm
                     // targets, though. For more information on wasm see the
                     // is_like_wasm check in hir_analysis/src/collect.rs
-                    if !self.tcx.sess.target.options.is_like_wasm
-                        && !self
-                            .tcx
-                            .codegen_fn_attrs(func_did)
-                            .target_features
-                            .iter()
-                            .all(|feature| self.body_target_features.contains(feature))
-                    {
-                        self.requires_unsafe(expr.span, CallToFunctionWith(func_did));
+                    let missing: Vec<_> = self
+                        .tcx
+                        .codegen_fn_attrs(func_did)
+                        .target_features
+                        .iter()
+                        .copied()
+                        .filter(|feature| !self.body_target_features.contains(feature))
+                        .collect();
+                    if !self.tcx.sess.target.options.is_like_wasm && !missing.is_empty() {
+                        self.requires_unsafe(expr.span, CallToFunctionWith(func_did, missing));
                     }
                 } else {
                     tracing::debug!("is not unsafe")
@@ -438,12 +482,13 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
             ExprKind::Deref { arg } => {
                 if let ExprKind::StaticRef { def_id, .. } = self.thir[arg].kind {
                     if self.tcx.is_mutable_static(def_id) {
-                        self.requires_unsafe(expr.span, UseOfMutableStatic);
+                        self.requires_unsafe(expr.span, UseOfMutableStatic(def_id));
                     } else if self.tcx.is_foreign_item(def_id) {
-                        self.requires_unsafe(expr.span, UseOfExternStatic);
+                        self.requires_unsafe(expr.span, UseOfExternStatic(def_id));
                     }
                 } else if self.thir[arg].ty.is_unsafe_ptr() {
-                    self.requires_unsafe(expr.span, DerefOfRawPointer);
+                    let pointee = expr.ty.to_string();
+                    self.requires_unsafe(expr.span, DerefOfRawPointer(Symbol::intern(&pointee)));
                 }
             }
             ExprKind::InlineAsm { .. } => {
@@ -458,7 +503,7 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                 base: _,
             }) => match self.tcx.layout_scalar_valid_range(adt_def.did()) {
                 (Bound::Unbounded, Bound::Unbounded) => {}
-                _ => self.requires_unsafe(expr.span, InitializingTypeWith),
+                _ => self.requires_unsafe(expr.span, InitializingTypeWith(adt_def.did())),
             },
             ExprKind::Closure(box ClosureExpr {
                 closure_id,
@@ -467,16 +512,7 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                 movability: _,
                 fake_reads: _,
             }) => {
-                let closure_def = if let Some((did, const_param_id)) =
-                    ty::WithOptConstParam::try_lookup(closure_id, self.tcx)
-                {
-                    ty::WithOptConstParam {
-                        did,
-                        const_param_did: Some(const_param_id),
-                    }
-                } else {
-                    ty::WithOptConstParam::unknown(closure_id)
-                };
+                let closure_def = with_opt_const_param(self.tcx, closure_id);
                 let (closure_thir, expr) = self.tcx.thir_body(closure_def).unwrap_or_else(|_| {
                     (self.tcx.alloc_steal_thir(Thir::new()), ExprId::from_u32(0))
                 });
@@ -486,13 +522,14 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                     thir: closure_thir,
                     hir_context,
                     violations: self.violations.clone(),
+                    unsafe_blocks: self.unsafe_blocks.clone(),
                     ..*self
                 };
                 closure_visitor.visit_expr(&closure_thir[expr]);
                 // Unsafe blocks can be used in closures, make sure to take it into account
                 self.safety_context = closure_visitor.safety_context;
             }
-            ExprKind::Field { lhs, .. } => {
+            ExprKind::Field { lhs, name, .. } => {
                 let lhs = &self.thir[lhs];
                 if let ty::Adt(adt_def, _) = lhs.ty.kind() && adt_def.is_union() {
                     if let Some((assigned_ty, assignment_span)) = self.assignment_info {
@@ -501,7 +538,11 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                             self.tcx.sess.delay_span_bug(assignment_span, format!("union fields that need dropping should be impossible: {assigned_ty}"));
                         }
                     } else {
-                        self.requires_unsafe(expr.span, AccessToUnionField);
+                        let field = adt_def.non_enum_variant().fields[name.index()].name;
+                        self.requires_unsafe(
+                            expr.span,
+                            AccessToUnionField(adt_def.did(), Some(field), self.union_access),
+                        );
                     }
                 }
             }
@@ -510,8 +551,8 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                 // First, check whether we are mutating a layout constrained field
                 let mut visitor = LayoutConstrainedPlaceVisitor::new(self.thir, self.tcx);
                 visit::walk_expr(&mut visitor, lhs);
-                if visitor.found {
-                    self.requires_unsafe(expr.span, MutationOfLayoutConstrainedField);
+                if let Some(adt) = visitor.found {
+                    self.requires_unsafe(expr.span, MutationOfLayoutConstrainedField(adt));
                 }
 
                 // Second, check for accesses to union fields
@@ -524,20 +565,24 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
                     return; // we have already visited everything by now
                 }
             }
+            ExprKind::AddressOf { mutability, .. } => {
+                self.union_access = UnionAccess::Borrow(mutability);
+            }
             ExprKind::Borrow { borrow_kind, arg } => {
+                self.union_access = UnionAccess::Borrow(borrow_kind.to_mutbl_lossy());
                 let mut visitor = LayoutConstrainedPlaceVisitor::new(self.thir, self.tcx);
                 visit::walk_expr(&mut visitor, expr);
-                if visitor.found {
+                if let Some(adt) = visitor.found {
                     match borrow_kind {
                         BorrowKind::Shallow | BorrowKind::Shared | BorrowKind::Unique
                             if !self.thir[arg]
                                 .ty
                                 .is_freeze(self.tcx.at(self.thir[arg].span), self.param_env) =>
                         {
-                            self.requires_unsafe(expr.span, BorrowOfLayoutConstrainedField)
+                            self.requires_unsafe(expr.span, BorrowOfLayoutConstrainedField(adt))
                         }
                         BorrowKind::Mut { .. } => {
-                            self.requires_unsafe(expr.span, MutationOfLayoutConstrainedField)
+                            self.requires_unsafe(expr.span, MutationOfLayoutConstrainedField(adt))
                         }
                         BorrowKind::Shallow | BorrowKind::Shared | BorrowKind::Unique => {}
                     }
@@ -546,7 +591,10 @@ impl<'a, 'tcx> Visitor<'a, 'tcx> for UnsafetyVisitor<'a, 'tcx> {
             ExprKind::Let { expr: expr_id, .. } => {
                 let let_expr = &self.thir[expr_id];
                 if let ty::Adt(adt_def, _) = let_expr.ty.kind() && adt_def.is_union() {
-                    self.requires_unsafe(expr.span, AccessToUnionField);
+                    self.requires_unsafe(
+                        expr.span,
+                        AccessToUnionField(adt_def.did(), None, UnionAccess::Pattern),
+                    );
                 }
             }
             _ => {}
@@ -591,39 +639,115 @@ impl BodyUnsafety {
     }
 }
 
-#[derive(Clone, Copy, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug)]
 pub enum UnsafeOpKind {
     CallToUnsafeFunction(Option<DefId>),
     UseOfInlineAssembly,
-    InitializingTypeWith,
-    UseOfMutableStatic,
-    UseOfExternStatic,
-    DerefOfRawPointer,
-    AccessToUnionField,
-    MutationOfLayoutConstrainedField,
-    BorrowOfLayoutConstrainedField,
-    CallToFunctionWith(DefId),
+    /// Constructing the layout constrained type.
+    InitializingTypeWith(DefId),
+    UseOfMutableStatic(DefId),
+    UseOfExternStatic(DefId),
+    /// With the pointee type.
+    DerefOfRawPointer(Symbol),
+    /// Accessing a field of the union, or matching on it as a whole if `None`.
+    AccessToUnionField(DefId, Option<Symbol>, UnionAccess),
+    /// With the layout constrained type the field is in.
+    MutationOfLayoutConstrainedField(DefId),
+    BorrowOfLayoutConstrainedField(DefId),
+    /// With the target features the caller doesn't enable.
+    CallToFunctionWith(DefId, Vec<Symbol>),
+    /// `#[no_mangle]`, `#[export_name]` or `#[link_section]` on an item, with the name of the
+    /// attribute.
+    UnsafeAttribute(Symbol),
+    /// An item declared in an `extern` block.
+    UnsafeExternBlock,
+    /// The function has to be unsafe to match the `unsafe fn` trait method it implements.
+    InheritedFromInterface(DefId),
     /// Function is chosen to be unsafe, when it probably doesn't need to be. This may be because it does some kind of logic stuff
     ChoosenUnsafe,
 }
 
 use UnsafeOpKind::*;
 
+/// How a union field is accessed.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UnionAccess {
+    Read,
+    /// A reference or raw pointer to it is taken.
+    Borrow(hir::Mutability),
+    /// It is bound or matched on in a pattern.
+    Pattern,
+}
+
+impl UnionAccess {
+    pub fn description(&self) -> &'static str {
+        match self {
+            UnionAccess::Read => "read",
+            UnionAccess::Borrow(hir::Mutability::Not) => "borrow",
+            UnionAccess::Borrow(hir::Mutability::Mut) => "mutable borrow",
+            UnionAccess::Pattern => "pattern match",
+        }
+    }
+}
+
 impl UnsafeOpKind {
+    /// The names of all kinds, as used in `whynot.toml`.
+    pub const NAMES: &'static [&'static str] = &[
+        "CallToUnsafeFunction",
+        "UseOfInlineAssembly",
+        "InitializingTypeWith",
+        "UseOfMutableStatic",
+        "UseOfExternStatic",
+        "DerefOfRawPointer",
+        "AccessToUnionField",
+        "MutationOfLayoutConstrainedField",
+        "BorrowOfLayoutConstrainedField",
+        "CallToFunctionWith",
+        "UnsafeAttribute",
+        "UnsafeExternBlock",
+        "InheritedFromInterface",
+        "ChoosenUnsafe",
+    ];
+
+    /// The name of the kind, without any payload.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CallToUnsafeFunction(..) => "CallToUnsafeFunction",
+            UseOfInlineAssembly => "UseOfInlineAssembly",
+            InitializingTypeWith(..) => "InitializingTypeWith",
+            UseOfMutableStatic(..) => "UseOfMutableStatic",
+            UseOfExternStatic(..) => "UseOfExternStatic",
+            DerefOfRawPointer(..) => "DerefOfRawPointer",
+            AccessToUnionField(..) => "AccessToUnionField",
+            MutationOfLayoutConstrainedField(..) => "MutationOfLayoutConstrainedField",
+            BorrowOfLayoutConstrainedField(..) => "BorrowOfLayoutConstrainedField",
+            CallToFunctionWith(..) => "CallToFunctionWith",
+            UnsafeAttribute(..) => "UnsafeAttribute",
+            UnsafeExternBlock => "UnsafeExternBlock",
+            InheritedFromInterface(..) => "InheritedFromInterface",
+            ChoosenUnsafe => "ChoosenUnsafe",
+        }
+    }
+
     pub fn simple_description(&self) -> &'static str {
         match self {
             CallToUnsafeFunction(..) => "call to unsafe function",
             UseOfInlineAssembly => "use of inline assembly",
-            InitializingTypeWith => "initializing type with `rustc_layout_scalar_valid_range` attr",
-            UseOfMutableStatic => "use of mutable static",
-            UseOfExternStatic => "use of extern static",
-            DerefOfRawPointer => "dereference of raw pointer",
-            AccessToUnionField => "access to union field",
-            MutationOfLayoutConstrainedField => "mutation of layout constrained field",
-            BorrowOfLayoutConstrainedField => {
+            InitializingTypeWith(..) => {
+                "initializing type with `rustc_layout_scalar_valid_range` attr"
+            }
+            UseOfMutableStatic(..) => "use of mutable static",
+            UseOfExternStatic(..) => "use of extern static",
+            DerefOfRawPointer(..) => "dereference of raw pointer",
+            AccessToUnionField(..) => "access to union field",
+            MutationOfLayoutConstrainedField(..) => "mutation of layout constrained field",
+            BorrowOfLayoutConstrainedField(..) => {
                 "borrow of layout constrained field with interior mutability"
             }
             CallToFunctionWith(..) => "call to function with `#[target_feature]`",
+            UnsafeAttribute(..) => "use of unsafe attribute",
+            UnsafeExternBlock => "declaration in an `extern` block",
+            InheritedFromInterface(..) => "unsafety inherited from the interface",
             ChoosenUnsafe => "unsafe by choice",
         }
     }
@@ -646,47 +770,86 @@ impl UnsafeOpKind {
                 Cow::Borrowed(self.simple_description()),
                 "inline assembly is entirely unchecked and can cause undefined behavior",
             ),
-            InitializingTypeWith => (
-                Cow::Borrowed(self.simple_description()),
+            InitializingTypeWith(did) => (
+                Cow::from(format!(
+                    "initializing `{}`, which is only valid in `{}`",
+                    tcx.def_path_str(*did),
+                    valid_range(tcx, *did)
+                )),
                 "initializing a layout restricted type's field with a value outside the valid \
                  range is undefined behavior",
             ),
-            UseOfMutableStatic => (
-                Cow::Borrowed(self.simple_description()),
+            UseOfMutableStatic(did) => (
+                Cow::from(format!("use of mutable static `{}`", tcx.def_path_str(*did))),
                 "mutable statics can be mutated by multiple threads: aliasing violations or data \
                  races will cause undefined behavior",
             ),
-            UseOfExternStatic => (
-                Cow::Borrowed(self.simple_description()),
+            UseOfExternStatic(did) => (
+                Cow::from(format!("use of extern static `{}`", tcx.def_path_str(*did))),
                 "extern statics are not controlled by the Rust type system: invalid data, \
                  aliasing violations or data races will cause undefined behavior",
             ),
-            DerefOfRawPointer => (
-                Cow::Borrowed(self.simple_description()),
+            DerefOfRawPointer(pointee) => (
+                Cow::from(format!("dereference of raw pointer to `{pointee}`")),
                 "raw pointers may be null, dangling or unaligned; they can violate aliasing rules \
                  and cause data races: all of these are undefined behavior",
             ),
-            AccessToUnionField => (
-                Cow::Borrowed(self.simple_description()),
+            AccessToUnionField(did, field, access) => (
+                Cow::from(match field {
+                    Some(field) => format!(
+                        "{} of union field `{}::{field}`",
+                        access.description(),
+                        tcx.item_name(*did)
+                    ),
+                    None => format!("{} of union `{}`", access.description(), tcx.item_name(*did)),
+                }),
                 "the field may not be properly initialized: using uninitialized data will cause \
                  undefined behavior",
             ),
-            MutationOfLayoutConstrainedField => (
-                Cow::Borrowed(self.simple_description()),
+            MutationOfLayoutConstrainedField(did) => (
+                Cow::from(format!(
+                    "mutation of a field of `{}`, which is only valid in `{}`",
+                    tcx.def_path_str(*did),
+                    valid_range(tcx, *did)
+                )),
                 "mutating layout constrained fields cannot statically be checked for valid values",
             ),
-            BorrowOfLayoutConstrainedField => (
-                Cow::Borrowed(self.simple_description()),
+            BorrowOfLayoutConstrainedField(did) => (
+                Cow::from(format!(
+                    "borrow of a field of `{}` with interior mutability, which is only valid in \
+                     `{}`",
+                    tcx.def_path_str(*did),
+                    valid_range(tcx, *did)
+                )),
                 "references to fields of layout constrained fields lose the constraints. Coupled \
                  with interior mutability, the field can be changed to invalid values",
             ),
-            CallToFunctionWith(did) => (
+            CallToFunctionWith(did, missing) => (
                 Cow::from(format!(
-                    "call to function `{}` with `#[target_feature]`",
-                    tcx.def_path_str(*did)
+                    "call to function `{}` with `#[target_feature]`, the caller doesn't enable {}",
+                    tcx.def_path_str(*did),
+                    missing.iter().map(|feature| format!("`{feature}`")).join(", ")
                 )),
                 "can only be called if the required target features are available",
             ),
+            UnsafeAttribute(name) => (
+                Cow::from(format!("unsafe attribute `#[{name}]`")),
+                "the linker trusts exported symbols and link sections: a symbol colliding with \
+                 another one, or an item in the wrong section, will cause undefined behavior",
+            ),
+            UnsafeExternBlock => (
+                Cow::Borrowed(self.simple_description()),
+                "the declaration is trusted to match the foreign definition: a wrong signature or \
+                 type will cause undefined behavior",
+            ),
+            InheritedFromInterface(did) => (
+                Cow::from(format!(
+                    "implementation of the unsafe trait method `{}`",
+                    tcx.def_path_str(*did)
+                )),
+                "the unsafety is inherited from the trait, it can't be removed without changing \
+                 the trait",
+            ),
             ChoosenUnsafe => (
                 Cow::Borrowed(self.simple_description()),
                 "can only be called if the required target features are available",
@@ -695,13 +858,108 @@ impl UnsafeOpKind {
     }
 }
 
+/// The valid range of a layout constrained type, e.g. `1..`.
+fn valid_range(tcx: TyCtxt<'_>, did: DefId) -> String {
+    let (start, end) = tcx.layout_scalar_valid_range(did);
+    let start = match start {
+        Bound::Included(start) => start.to_string(),
+        Bound::Excluded(start) => (start + 1).to_string(),
+        Bound::Unbounded => String::new(),
+    };
+    match end {
+        Bound::Included(end) => format!("{start}..={end}"),
+        Bound::Excluded(end) => format!("{start}..{end}"),
+        Bound::Unbounded => format!("{start}.."),
+    }
+}
+
+/// An explicit `unsafe {}` block found while visiting a body.
+#[derive(Clone, Debug)]
+pub struct UnsafeBlock {
+    pub hir_id: hir::HirId,
+    pub span: Span,
+    /// The body owner the block was found in.
+    pub owner: LocalDefId,
+    /// The unsafe operations done in this block. Empty if the block is unnecessary.
+    pub ops: Vec<(UnsafeOpKind, Span)>,
+}
+
+/// Everything found by the visitor in a single body.
+#[derive(Debug, Default)]
+pub struct UnsafetyReport {
+    pub violations: Vec<(UnsafeOpKind, LocalDefId, Span)>,
+    pub unsafe_blocks: Vec<UnsafeBlock>,
+}
+
+/// Resolve a call to a trait method to the method in the impl that will run, if it is known.
+pub fn resolve_callee<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    param_env: ParamEnv<'tcx>,
+    did: DefId,
+    substs: SubstsRef<'tcx>,
+) -> DefId {
+    if tcx.trait_of_item(did).is_none() {
+        return did;
+    }
+    match ty::Instance::resolve(tcx, param_env, did, substs) {
+        Ok(Some(instance)) if !matches!(instance.def, ty::InstanceDef::Virtual(..)) => {
+            instance.def_id()
+        }
+        _ => did,
+    }
+}
+
+/// The methods implementing the trait method `did`, in every impl of its trait that is known.
+pub fn candidate_impls(tcx: TyCtxt<'_>, did: DefId) -> Vec<DefId> {
+    let Some(trait_id) = tcx.trait_of_item(did) else {
+        return vec![];
+    };
+    tcx.all_impls(trait_id)
+        .filter_map(|impl_id| tcx.impl_item_implementor_ids(impl_id).get(&did).copied())
+        .collect()
+}
+
+/// Whether `did` is an `unsafe fn`, declared in this crate or not.
+pub fn is_unsafe_fn(tcx: TyCtxt<'_>, did: DefId) -> bool {
+    match did.as_local() {
+        Some(did) => {
+            let hir = tcx.hir();
+            hir.fn_sig_by_hir_id(hir.local_def_id_to_hir_id(did))
+                .map_or(false, |sig| sig.header.unsafety == hir::Unsafety::Unsafe)
+        }
+        None => {
+            matches!(tcx.def_kind(did), hir::def::DefKind::Fn | hir::def::DefKind::AssocFn)
+                && tcx.fn_sig(did).unsafety() == hir::Unsafety::Unsafe
+        }
+    }
+}
+
+/// Pairs `did` with its const param, if it is the body of a const argument.
+pub fn with_opt_const_param(tcx: TyCtxt<'_>, did: LocalDefId) -> ty::WithOptConstParam<LocalDefId> {
+    if let Some((did, const_param_id)) = ty::WithOptConstParam::try_lookup(did, tcx) {
+        ty::WithOptConstParam {
+            did,
+            const_param_did: Some(const_param_id),
+        }
+    } else {
+        ty::WithOptConstParam::unknown(did)
+    }
+}
+
 pub fn check_unsafety<'tcx>(
     tcx: TyCtxt<'tcx>,
     def: ty::WithOptConstParam<LocalDefId>,
 ) -> Vec<(UnsafeOpKind, LocalDefId, Span)> {
+    check_body_unsafety(tcx, def).violations
+}
+
+pub fn check_body_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    def: ty::WithOptConstParam<LocalDefId>,
+) -> UnsafetyReport {
     // THIR unsafeck is gated under `-Z thir-unsafeck`
     if !tcx.sess.opts.unstable_opts.thir_unsafeck {
-        return vec![];
+        return <_>::default();
     }
 
     // Closures are handled by their owner, if it has a body
@@ -709,16 +967,16 @@ pub fn check_unsafety<'tcx>(
         let hir = tcx.hir();
         let owner = hir.enclosing_body_owner(hir.local_def_id_to_hir_id(def.did));
         tcx.ensure().thir_check_unsafety(owner);
-        return vec![];
+        return <_>::default();
     }
 
     let Ok((thir, expr)) = tcx.thir_body(def) else {
-        return vec![]
+        return <_>::default()
     };
     let thir = &thir.borrow();
     // If `thir` is empty, a type error occurred, skip this body.
     if thir.exprs.is_empty() {
-        return vec![];
+        return <_>::default();
     }
     tracing::debug!("oops");
     let hir_id = tcx.hir().local_def_id_to_hir_id(def.did);
@@ -746,15 +1004,23 @@ pub fn check_unsafety<'tcx>(
         body_unsafety,
         body_target_features,
         assignment_info: None,
-        in_union_destructure: false,
+        in_union_destructure: None,
+        union_access: UnionAccess::Read,
         param_env: tcx.param_env(def.did),
-        inside_adt: false,
+        inside_adt: None,
         violations: <_>::default(),
+        unsafe_blocks: <_>::default(),
         current_did: def.did,
     };
     visitor.visit_expr(&thir[expr]);
-    Arc::try_unwrap(visitor.violations)
-        .unwrap()
-        .into_inner()
-        .unwrap()
+    UnsafetyReport {
+        violations: Arc::try_unwrap(visitor.violations)
+            .unwrap()
+            .into_inner()
+            .unwrap(),
+        unsafe_blocks: Arc::try_unwrap(visitor.unsafe_blocks)
+            .unwrap()
+            .into_inner()
+            .unwrap(),
+    }
 }
@@ -0,0 +1,248 @@
+//! Check that unsafe blocks and unsafe operations in unsafe fns have `// SAFETY:` comments, and
+//! show which operations each comment has to justify.
+use std::{ffi::OsString, io::Write};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::Result;
+use itertools::Itertools;
+use rustc_middle::ty::TyCtxt;
+use rustc_span::{source_map::SourceMap, Span};
+
+use crate::{
+    report::Files,
+    run::cargo_check,
+    safe::unsafety_visitor::{self, UnsafeOpKind},
+};
+
+pub(crate) fn run(args: crate::opts::SafetyCommentsArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    tracing::debug!("checking safety comments");
+    cargo_check(
+        "safety-comments",
+        None,
+        &args.package,
+        Some("-Zthir-unsafeck"),
+        rem,
+    )
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("safety-comments")
+    );
+    tracing::trace!("in whynot safety-comments rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut SafetyCommentsCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+pub struct SafetyCommentsCallback;
+
+impl SafetyCommentsCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let hir = tcx.hir();
+        let sm = tcx.sess.source_map();
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut files = Files::new(sm);
+        let (mut missing, mut mixed, mut documented) = (0, 0, 0);
+
+        for did in hir.body_owners() {
+            if tcx.is_closure(did.to_def_id()) {
+                continue;
+            }
+            let report = unsafety_visitor::check_body_unsafety(
+                tcx,
+                unsafety_visitor::with_opt_const_param(tcx, did),
+            );
+            let is_unsafe_fn = unsafety_visitor::is_unsafe_fn(tcx, did.to_def_id());
+
+            // Every explicit `unsafe {}` block, in safe and unsafe fns alike.
+            for block in &report.unsafe_blocks {
+                if block.ops.is_empty() {
+                    continue;
+                }
+                let ops = justified_ops(tcx, &block.ops);
+                let diag = match safety_comment(sm, block.span) {
+                    None => {
+                        missing += 1;
+                        Diagnostic::warning()
+                            .with_message("unsafe block has no `// SAFETY:` comment")
+                            .with_notes(vec![format!("a comment should justify: {ops}")])
+                    }
+                    Some(comment) => {
+                        documented += 1;
+                        let kinds = block
+                            .ops
+                            .iter()
+                            .map(|(kind, _)| kind.name())
+                            .unique()
+                            .count();
+                        let diag = if kinds > 1 {
+                            mixed += 1;
+                            Diagnostic::warning().with_message(format!(
+                                "`// SAFETY:` comment covers {kinds} different kinds of unsafe operations"
+                            ))
+                        } else {
+                            Diagnostic::note().with_message("`// SAFETY:` comment")
+                        };
+                        diag.with_notes(vec![
+                            format!("the comment reads: {comment}"),
+                            format!("it has to justify: {ops}"),
+                        ])
+                    }
+                };
+                let mut labels = vec![];
+                labels.extend(files.label(block.span, LabelStyle::Primary, "unsafe block"));
+                for (kind, span) in &block.ops {
+                    labels.extend(files.label(
+                        *span,
+                        LabelStyle::Secondary,
+                        kind.description_and_note(tcx).0,
+                    ));
+                }
+                files.emit(&mut io, &diag.with_labels(labels))?;
+            }
+
+            // Operations directly in the body of an unsafe fn, outside of any unsafe block.
+            if !is_unsafe_fn {
+                continue;
+            }
+            for (kind, _, span) in &report.violations {
+                if report
+                    .unsafe_blocks
+                    .iter()
+                    .any(|block| block.span.contains(*span))
+                {
+                    continue;
+                }
+                let diag = match safety_comment(sm, *span) {
+                    None => {
+                        missing += 1;
+                        Diagnostic::warning().with_message(format!(
+                            "{} in unsafe fn has no `// SAFETY:` comment",
+                            kind.description_and_note(tcx).0
+                        ))
+                    }
+                    Some(comment) => {
+                        documented += 1;
+                        Diagnostic::note()
+                            .with_message("`// SAFETY:` comment")
+                            .with_notes(vec![format!("the comment reads: {comment}")])
+                    }
+                };
+                let labels = files
+                    .label(*span, LabelStyle::Primary, kind.simple_description())
+                    .into_iter()
+                    .collect();
+                files.emit(&mut io, &diag.with_labels(labels))?;
+            }
+        }
+
+        writeln!(
+            io,
+            "{documented} documented, {missing} missing `// SAFETY:` comments, {mixed} comments covering several kinds of unsafe operations"
+        )?;
+        if missing > 0 {
+            crate::run::report_failure(format!("{missing} missing `// SAFETY:` comments"))?;
+        }
+        Ok(())
+    }
+}
+
+/// List the operations a comment has to justify.
+fn justified_ops(tcx: TyCtxt<'_>, ops: &[(UnsafeOpKind, Span)]) -> String {
+    ops.iter()
+        .map(|(kind, _)| kind.description_and_note(tcx).0)
+        .unique()
+        .join(", ")
+}
+
+/// The `// SAFETY:` comment directly above the line `span` starts on, if any.
+fn safety_comment(sm: &SourceMap, span: Span) -> Option<String> {
+    let pos = sm.lookup_byte_offset(span.lo());
+    let src = pos.sf.src.as_deref()?;
+    let comment = comment_above(&src[..pos.pos.0 as usize])?;
+    comment.contains("SAFETY:").then_some(comment)
+}
+
+/// The comment directly preceding the end of `before`, skipping attributes.
+///
+/// Unless `before` ends with a newline, its last line is the code on the same line before the
+/// item, which may itself end with a comment.
+fn comment_above(before: &str) -> Option<String> {
+    let mut lines = before.lines().rev().map(str::trim);
+    let mut comment = vec![];
+    if !before.ends_with('\n')
+        && let Some(same_line) = lines.next()
+        && let Some((_, c)) = same_line.split_once("/*")
+    {
+        comment.push(c.trim_end_matches("*/").trim());
+    }
+    let mut in_block_comment = false;
+    for line in lines {
+        if in_block_comment {
+            comment.push(line.trim_start_matches("/*").trim_start_matches('*').trim());
+            in_block_comment = !line.starts_with("/*");
+        } else if let Some(c) = line.strip_prefix("//") {
+            comment.push(c.trim_start_matches('/').trim());
+        } else if line.ends_with("*/") {
+            comment.push(line.trim_end_matches("*/").trim_start_matches("/*").trim());
+            in_block_comment = !line.starts_with("/*");
+        } else if line.starts_with("#[") {
+            continue;
+        } else {
+            break;
+        }
+    }
+    comment.reverse();
+    let comment = comment.join(" ");
+    (!comment.trim().is_empty()).then(|| comment.trim().to_string())
+}
+
+#[test]
+#[cfg(test)]
+fn test_comment_above() {
+    assert_eq!(
+        comment_above("fn foo() {\n    // SAFETY: a is valid\n    // for reads\n    let b = "),
+        Some("SAFETY: a is valid for reads".to_string())
+    );
+    assert_eq!(
+        comment_above("fn foo() {\n    /* SAFETY:\n     * a is valid\n     */\n    "),
+        Some("SAFETY: a is valid".to_string())
+    );
+    assert_eq!(
+        comment_above("    // SAFETY: fine\n    #[allow(unused)]\n    "),
+        Some("SAFETY: fine".to_string())
+    );
+    assert_eq!(
+        comment_above("    // SAFETY: not adjacent\n    let a = 1;\n    let b = "),
+        None
+    );
+    assert_eq!(
+        comment_above("    let b = /* SAFETY: inline */ "),
+        Some("SAFETY: inline".to_string())
+    );
+}
+
+impl rustc_driver::Callbacks for SafetyCommentsCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    SafetyCommentsCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
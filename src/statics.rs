@@ -0,0 +1,573 @@
+//! Audit `static mut`s and extern statics: every place they are used, whether that place can run
+//! on another thread or in a signal handler, and what the static could be replaced with.
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsString,
+    io::Write,
+};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::Result;
+use itertools::Itertools;
+use rustc_hir::{self as hir, def::DefKind};
+use rustc_middle::{
+    mir::BorrowKind,
+    thir::{
+        visit::{self, Visitor},
+        *,
+    },
+    ty::{self, TyCtxt},
+};
+use rustc_span::{
+    def_id::{DefId, LocalDefId},
+    Span, Symbol,
+};
+
+use crate::{
+    audit::inventory::location,
+    callers::calls_in,
+    report::Files,
+    run::cargo_check,
+    safe::unsafety_visitor::{self, UnsafeOpKind},
+};
+
+pub(crate) fn run(args: crate::opts::StaticsArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    tracing::debug!("auditing statics");
+    cargo_check("statics", None, &args.package, Some("-Zthir-unsafeck"), rem)
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("statics")
+    );
+    tracing::trace!("in whynot statics rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut StaticsCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+/// How a static is used at a site.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    /// Assigned to, or a field or element of it is.
+    Write,
+    Borrow(hir::Mutability),
+}
+
+impl Access {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Borrow(hir::Mutability::Not) => "shared borrow",
+            Access::Borrow(hir::Mutability::Mut) => "mutable borrow",
+        }
+    }
+
+    fn mutates(&self) -> bool {
+        matches!(self, Access::Write | Access::Borrow(hir::Mutability::Mut))
+    }
+}
+
+/// Code that may run concurrently with the rest of the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Context {
+    /// A closure or fn passed to `thread::spawn` and the like.
+    Thread,
+    /// A closure or fn installed as a signal handler.
+    SignalHandler,
+}
+
+/// A closure or fn handed to another thread or installed as a signal handler.
+#[derive(Debug)]
+pub struct Root {
+    pub context: Context,
+    pub did: LocalDefId,
+}
+
+/// A use of a static found by the unsafety visitor.
+#[derive(Debug)]
+pub struct Site {
+    pub owner: LocalDefId,
+    pub span: Span,
+    pub access: Access,
+    pub contexts: Vec<Context>,
+}
+
+/// Closures and fns passed to these fns run on another thread.
+const THREAD_FNS: [&str; 4] = [
+    "std::thread::spawn",
+    "std::thread::Builder::spawn",
+    "std::thread::Builder::spawn_scoped",
+    "std::thread::Scope::spawn",
+];
+/// Signal handlers are installed with these fns.
+const SIGNAL_FNS: [&str; 2] = ["libc::signal", "libc::sigaction"];
+/// Signal handlers are installed with the `register*` fns of these crates, e.g.
+/// `signal_hook::low_level::register`.
+const SIGNAL_CRATES: [&str; 2] = ["signal_hook", "signal_hook_registry"];
+/// Signal handlers stored in these fields of `sigaction` are installed too.
+const SIGNAL_FIELDS: [&str; 2] = ["sa_handler", "sa_sigaction"];
+
+/// The path of `did`, with methods of inherent impls under their type, e.g.
+/// `std::thread::Builder::spawn`.
+fn path(tcx: TyCtxt<'_>, did: DefId) -> String {
+    if let Some(impl_) = tcx.impl_of_method(did)
+        && tcx.trait_id_of_impl(impl_).is_none()
+        && let ty::Adt(adt, _) = tcx.type_of(impl_).kind()
+    {
+        format!("{}::{}", tcx.def_path_str(adt.did()), tcx.item_name(did))
+    } else {
+        tcx.def_path_str(did)
+    }
+}
+
+/// Where the closures and fns passed to `callee` run, if it is a known way to spawn a thread or
+/// install a signal handler.
+fn call_context(tcx: TyCtxt<'_>, callee: DefId) -> Option<Context> {
+    let path = path(tcx, callee);
+    if THREAD_FNS.contains(&path.as_str()) {
+        Some(Context::Thread)
+    } else if SIGNAL_FNS.contains(&path.as_str())
+        || SIGNAL_CRATES.contains(&tcx.crate_name(callee.krate).as_str())
+            && tcx.item_name(callee).as_str().starts_with("register")
+    {
+        Some(Context::SignalHandler)
+    } else {
+        None
+    }
+}
+
+/// Record how statics are accessed in the body of `did` and its closures, and the code it hands
+/// to other threads or signal handlers.
+fn scan(
+    tcx: TyCtxt<'_>,
+    did: LocalDefId,
+    accesses: &mut HashMap<Span, Access>,
+    roots: &mut Vec<Root>,
+) {
+    struct Scan<'a, 'tcx> {
+        tcx: TyCtxt<'tcx>,
+        thir: &'a Thir<'tcx>,
+        accesses: &'a mut HashMap<Span, Access>,
+        roots: &'a mut Vec<Root>,
+    }
+
+    impl<'a, 'tcx> Scan<'a, 'tcx> {
+        /// Record `access` if the place `expr` is (part of) a static.
+        fn record(&mut self, expr: ExprId, access: Access) {
+            match self.thir[expr].kind {
+                ExprKind::Scope { value: place, .. }
+                | ExprKind::Field { lhs: place, .. }
+                | ExprKind::Index { lhs: place, .. } => self.record(place, access),
+                ExprKind::Deref { arg } => {
+                    if let ExprKind::StaticRef { .. } = self.thir[arg].kind {
+                        self.accesses.insert(self.thir[expr].span, access);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        /// Record `expr` as a root if it is a closure or a local fn, possibly coerced or cast
+        /// to a pointer like `handler as libc::sighandler_t`.
+        fn root(&mut self, expr: ExprId, context: Context) {
+            let expr = &self.thir[expr];
+            match expr.kind {
+                ExprKind::Scope { value: source, .. }
+                | ExprKind::Use { source }
+                | ExprKind::Pointer { source, .. }
+                | ExprKind::Cast { source } => self.root(source, context),
+                ExprKind::Closure(box ClosureExpr { closure_id, .. }) => {
+                    self.roots.push(Root {
+                        context,
+                        did: closure_id,
+                    });
+                }
+                _ => {
+                    if let &ty::FnDef(did, _) = expr.ty.kind()
+                        && let Some(did) = did.as_local()
+                    {
+                        self.roots.push(Root { context, did });
+                    }
+                }
+            }
+        }
+
+        /// The name of the field `expr` is an assignment to, if any.
+        fn field_name(&self, expr: ExprId) -> Option<Symbol> {
+            match self.thir[expr].kind {
+                ExprKind::Scope { value, .. } => self.field_name(value),
+                ExprKind::Field {
+                    lhs,
+                    variant_index,
+                    name,
+                } => {
+                    let ty::Adt(adt, _) = self.thir[lhs].ty.kind() else {
+                        return None;
+                    };
+                    Some(adt.variant(variant_index).fields[name.index()].name)
+                }
+                _ => None,
+            }
+        }
+    }
+
+    impl<'a, 'tcx> Visitor<'a, 'tcx> for Scan<'a, 'tcx> {
+        fn thir(&self) -> &'a Thir<'tcx> {
+            self.thir
+        }
+
+        fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+            match &expr.kind {
+                ExprKind::Assign { lhs, rhs } | ExprKind::AssignOp { lhs, rhs, .. } => {
+                    self.record(*lhs, Access::Write);
+                    if let Some(field) = self.field_name(*lhs)
+                        && SIGNAL_FIELDS.contains(&field.as_str())
+                    {
+                        self.root(*rhs, Context::SignalHandler);
+                    }
+                }
+                ExprKind::Borrow { borrow_kind, arg } => {
+                    let mutability = match borrow_kind {
+                        BorrowKind::Mut { .. } => hir::Mutability::Mut,
+                        _ => hir::Mutability::Not,
+                    };
+                    self.record(*arg, Access::Borrow(mutability));
+                }
+                ExprKind::AddressOf { mutability, arg } => {
+                    self.record(*arg, Access::Borrow(*mutability));
+                }
+                ExprKind::Call { fun, args, .. } => {
+                    if let &ty::FnDef(callee, _) = self.thir[*fun].ty.kind()
+                        && let Some(context) = call_context(self.tcx, callee)
+                    {
+                        for arg in args.iter() {
+                            self.root(*arg, context);
+                        }
+                    }
+                }
+                ExprKind::Adt(adt) => {
+                    let variant = adt.adt_def.variant(adt.variant_index);
+                    for field in adt.fields.iter() {
+                        let name = variant.fields[field.name.index()].name;
+                        if SIGNAL_FIELDS.contains(&name.as_str()) {
+                            self.root(field.expr, Context::SignalHandler);
+                        }
+                    }
+                }
+                ExprKind::Closure(closure) => {
+                    scan(self.tcx, closure.closure_id, self.accesses, self.roots);
+                }
+                _ => {}
+            }
+            visit::walk_expr(self, expr);
+        }
+    }
+
+    let Ok((thir, expr)) = tcx.thir_body(unsafety_visitor::with_opt_const_param(tcx, did)) else {
+        return;
+    };
+    let thir = &thir.borrow();
+    if thir.exprs.is_empty() {
+        return;
+    }
+    Scan {
+        tcx,
+        thir,
+        accesses,
+        roots,
+    }
+    .visit_expr(&thir[expr]);
+}
+
+/// The `static mut`s and extern statics declared in the crate.
+fn declared_statics(tcx: TyCtxt<'_>) -> Vec<DefId> {
+    let hir = tcx.hir();
+    let mut statics = vec![];
+    for id in hir.items() {
+        let item = hir.item(id);
+        match item.kind {
+            hir::ItemKind::Static(_, hir::Mutability::Mut, _) => {
+                statics.push(item.def_id.def_id.to_def_id());
+            }
+            hir::ItemKind::ForeignMod { items, .. } => statics.extend(
+                items
+                    .iter()
+                    .map(|item| item.id.def_id.def_id.to_def_id())
+                    .filter(|did| matches!(tcx.def_kind(*did), DefKind::Static(_))),
+            ),
+            _ => {}
+        }
+    }
+    statics
+}
+
+/// The fns that can run in each context: the roots, and all local fns they call.
+fn reachable(tcx: TyCtxt<'_>, roots: &[Root], context: Context) -> HashSet<LocalDefId> {
+    let mut reached = HashSet::new();
+    let mut stack: Vec<_> = roots
+        .iter()
+        .filter(|root| root.context == context)
+        .map(|root| root.did)
+        .collect();
+    while let Some(did) = stack.pop() {
+        if !reached.insert(did) {
+            continue;
+        }
+        let mut calls = vec![];
+        calls_in(tcx, did, &mut calls);
+        stack.extend(calls.into_iter().filter_map(|(callee, _)| callee.as_local()));
+    }
+    reached
+}
+
+/// What `static_` could be replaced with, given how it is used.
+fn suggestion(tcx: TyCtxt<'_>, static_: DefId, sites: &[Site]) -> String {
+    if tcx.is_foreign_item(static_) {
+        return "extern statics are owned by foreign code: wrap their uses in safe functions that \
+                document what the foreign code guarantees"
+            .to_string();
+    }
+    let ty = tcx.type_of(static_);
+    let in_signal_handler = sites
+        .iter()
+        .any(|site| site.contexts.contains(&Context::SignalHandler));
+    if let Some(atomic) = atomic(ty) {
+        return format!("replace it with a `static` `{atomic}`");
+    }
+    if in_signal_handler {
+        return "it is used in a signal handler, where only atomics can be used safely: let the \
+                handler only set an `AtomicBool`, and do the rest outside of it"
+            .to_string();
+    }
+    let writers: HashSet<_> = sites
+        .iter()
+        .filter(|site| site.access.mutates())
+        .map(|site| site.owner)
+        .collect();
+    match writers.iter().next() {
+        None => return "it is never mutated, make it a `static`".to_string(),
+        Some(writer) if writers.len() == 1 => {
+            return format!(
+                "it is only mutated in `{}`, if that initializes it use a `static` \
+                 `OnceLock<{ty}>`",
+                tcx.def_path_str(writer.to_def_id())
+            );
+        }
+        Some(_) => {}
+    }
+    if sites.iter().all(|site| site.contexts.is_empty()) {
+        format!(
+            "no spawned thread uses it: if every thread may have its own value, use a \
+             `thread_local!` `RefCell<{ty}>`, otherwise a `static` `Mutex<{ty}>`"
+        )
+    } else {
+        format!("it is used from spawned threads, use a `static` `Mutex<{ty}>`")
+    }
+}
+
+/// The atomic type that can replace a static of type `ty`.
+fn atomic(ty: ty::Ty<'_>) -> Option<String> {
+    match ty.kind() {
+        ty::Bool => Some("AtomicBool".to_string()),
+        ty::Int(ty::IntTy::I128) | ty::Uint(ty::UintTy::U128) => None,
+        ty::Int(int) => Some(format!("Atomic{}", capitalize(int.name_str()))),
+        ty::Uint(uint) => Some(format!("Atomic{}", capitalize(uint.name_str()))),
+        ty::RawPtr(pointee) => Some(format!("AtomicPtr<{}>", pointee.ty)),
+        _ => None,
+    }
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+pub struct StaticsCallback;
+
+impl StaticsCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let mut uses: HashMap<DefId, Vec<(LocalDefId, Span)>> = HashMap::new();
+        let mut accesses = HashMap::new();
+        let mut roots = vec![];
+        for did in tcx.hir().body_owners() {
+            if tcx.is_closure(did.to_def_id()) {
+                continue;
+            }
+            scan(tcx, did, &mut accesses, &mut roots);
+            let violations = unsafety_visitor::check_unsafety(
+                tcx,
+                unsafety_visitor::with_opt_const_param(tcx, did),
+            );
+            for (kind, owner, span) in violations {
+                if let UnsafeOpKind::UseOfMutableStatic(static_)
+                | UnsafeOpKind::UseOfExternStatic(static_) = kind
+                {
+                    uses.entry(static_).or_default().push((owner, span));
+                }
+            }
+        }
+        for static_ in declared_statics(tcx) {
+            uses.entry(static_).or_default();
+        }
+
+        let threads = reachable(tcx, &roots, Context::Thread);
+        let signal_handlers = reachable(tcx, &roots, Context::SignalHandler);
+        let hir = tcx.hir();
+        // Closures handed to threads are part of the fn they are in, so look at where they are.
+        let contexts = |owner: LocalDefId, span: Span| {
+            [
+                (Context::Thread, &threads),
+                (Context::SignalHandler, &signal_handlers),
+            ]
+            .into_iter()
+            .filter(|(context, reached)| {
+                reached.contains(&owner)
+                    || roots.iter().any(|root| {
+                        root.context == *context
+                            && hir
+                                .span_with_body(hir.local_def_id_to_hir_id(root.did))
+                                .contains(span)
+                    })
+            })
+            .map(|(context, _)| context)
+            .collect_vec()
+        };
+
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut files = Files::new(tcx.sess.source_map());
+        let sm = tcx.sess.source_map();
+        let (mut site_count, mut in_threads, mut in_signal_handlers) = (0, 0, 0);
+        let statics = uses
+            .into_iter()
+            .sorted_by_cached_key(|(static_, _)| tcx.def_path_str(*static_));
+        let static_count = statics.len();
+        for (static_, uses) in statics {
+            let sites = uses
+                .into_iter()
+                .unique_by(|(_, span)| *span)
+                .sorted_by_key(|(_, span)| span.lo())
+                .map(|(owner, span)| Site {
+                    owner,
+                    span,
+                    access: accesses.get(&span).copied().unwrap_or(Access::Read),
+                    contexts: contexts(owner, span),
+                })
+                .collect_vec();
+            site_count += sites.len();
+            in_threads += sites
+                .iter()
+                .filter(|site| site.contexts.contains(&Context::Thread))
+                .count();
+            in_signal_handlers += sites
+                .iter()
+                .filter(|site| site.contexts.contains(&Context::SignalHandler))
+                .count();
+
+            let mut labels = vec![];
+            if static_.is_local() {
+                labels.extend(files.label(tcx.def_span(static_), LabelStyle::Primary, ""));
+            }
+            for site in &sites {
+                let reach = site
+                    .contexts
+                    .iter()
+                    .map(|context| match context {
+                        Context::Thread => " (reachable from a spawned thread)",
+                        Context::SignalHandler => " (reachable from a signal handler)",
+                    })
+                    .join("");
+                labels.extend(files.label(
+                    site.span,
+                    LabelStyle::Secondary,
+                    format!(
+                        "{} in `{}`{reach}",
+                        site.access.description(),
+                        tcx.def_path_str(site.owner.to_def_id())
+                    ),
+                ));
+            }
+            let counts = [
+                Access::Read,
+                Access::Write,
+                Access::Borrow(hir::Mutability::Not),
+                Access::Borrow(hir::Mutability::Mut),
+            ]
+            .into_iter()
+            .filter_map(|access| {
+                let count = sites.iter().filter(|site| site.access == access).count();
+                (count > 0).then(|| format!("{count} {}s", access.description()))
+            })
+            .join(", ");
+            let mut notes = vec![];
+            if !static_.is_local() {
+                notes.push(format!("declared at {}", location(sm, tcx.def_span(static_))));
+            }
+            notes.push(suggestion(tcx, static_, &sites));
+            files.emit(
+                &mut io,
+                &Diagnostic::note()
+                    .with_message(format!(
+                        "{} `{}`: {}",
+                        if tcx.is_foreign_item(static_) {
+                            "extern static"
+                        } else {
+                            "static mut"
+                        },
+                        tcx.def_path_str(static_),
+                        if counts.is_empty() {
+                            "never used".to_string()
+                        } else {
+                            counts
+                        }
+                    ))
+                    .with_labels(labels)
+                    .with_notes(notes),
+            )?;
+        }
+        writeln!(
+            io,
+            "{static_count} mutable or extern statics used in {site_count} places, \
+             {in_threads} reachable from spawned threads and {in_signal_handlers} from signal \
+             handlers"
+        )?;
+        Ok(())
+    }
+}
+
+impl rustc_driver::Callbacks for StaticsCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    StaticsCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
+
+#[test]
+#[cfg(test)]
+fn test_capitalize() {
+    assert_eq!(capitalize("isize"), "Isize");
+    assert_eq!(capitalize("u32"), "U32");
+    assert_eq!(capitalize(""), "");
+}
@@ -0,0 +1,589 @@
+//! Audit unions: where each field is written and read, and the reads of a different field than
+//! the one last written, which are the likely type punning.
+use std::{collections::HashMap, ffi::OsString, io::Write};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::Result;
+use itertools::Itertools;
+use rustc_hir as hir;
+use rustc_middle::{
+    thir::{
+        visit::{self, Visitor},
+        *,
+    },
+    ty::{self, Ty, TyCtxt},
+};
+use rustc_span::{
+    def_id::{DefId, LocalDefId},
+    Span, Symbol,
+};
+
+use crate::{
+    report::Files,
+    run::cargo_check,
+    safe::unsafety_visitor::{self, UnionAccess, UnsafeOpKind},
+};
+
+pub(crate) fn run(args: crate::opts::UnionsArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    tracing::debug!("auditing unions");
+    cargo_check("unions", None, &args.package, Some("-Zthir-unsafeck"), rem)
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("unions")
+    );
+    tracing::trace!("in whynot unions rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut UnionsCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EventKind {
+    /// Assigning a field, or constructing the union.
+    Write,
+    /// Reading a field through a field expression.
+    Read,
+    /// Destructuring the union in a pattern, the span is the pattern's.
+    PatternRead,
+}
+
+/// A use of a union in a body, in the order they appear.
+#[derive(Debug)]
+struct Event {
+    kind: EventKind,
+    union: DefId,
+    field: Option<Symbol>,
+    /// The place the union is in, e.g. `self.repr`, if it is a place.
+    path: Option<String>,
+    span: Span,
+    owner: LocalDefId,
+    /// The `if`s and `match`es the event is in, outermost first, with the arm it is in.
+    branches: Vec<(usize, usize)>,
+}
+
+impl Event {
+    /// Whether `later` can run after this event, i.e. they are not in different arms of the
+    /// same `if` or `match`. Loops are not taken into account.
+    fn can_reach(&self, later: &Event) -> bool {
+        self.branches
+            .iter()
+            .zip(&later.branches)
+            .all(|((branch, arm), (later_branch, later_arm))| {
+                branch != later_branch || arm == later_arm
+            })
+    }
+
+    /// Whether this event always runs between `earlier` and `read`, so that it overwrites what
+    /// `earlier` wrote: it runs whenever `read` does, or whenever `earlier` does.
+    fn covers(&self, earlier: &Event, read: &Event) -> bool {
+        read.branches.starts_with(&self.branches) || earlier.branches.starts_with(&self.branches)
+    }
+}
+
+/// The writes to the place of the read `events[read]` that may be the last before it: one in
+/// each arm of an `if` or `match` that is not overwritten after it.
+///
+/// Only a write outside of the branches an earlier write is in overwrites it, so a write before
+/// an `if` whose arms all write again is still taken as possibly last.
+fn last_writes(events: &[Event], read: usize) -> Vec<&Event> {
+    let Some(path) = &events[read].path else {
+        return vec![];
+    };
+    let writes = events[..read]
+        .iter()
+        .filter(|event| {
+            event.kind == EventKind::Write
+                && event.path.as_ref() == Some(path)
+                && event.can_reach(&events[read])
+        })
+        .collect_vec();
+    writes
+        .iter()
+        .enumerate()
+        .filter(|(i, write)| {
+            !writes[i + 1..]
+                .iter()
+                .any(|later| later.covers(write, &events[read]))
+        })
+        .map(|(_, write)| *write)
+        .collect()
+}
+
+/// The union `ty` is, if any.
+fn union_of(ty: Ty<'_>) -> Option<DefId> {
+    ty.ty_adt_def()
+        .filter(|adt| adt.is_union())
+        .map(|adt| adt.did())
+}
+
+/// Collect the uses of unions in the body of `did` and its closures into `events`.
+fn scan(tcx: TyCtxt<'_>, did: LocalDefId, owner: LocalDefId, events: &mut Vec<Event>) {
+    struct Scan<'a, 'tcx> {
+        tcx: TyCtxt<'tcx>,
+        thir: &'a Thir<'tcx>,
+        owner: LocalDefId,
+        events: &'a mut Vec<Event>,
+        /// The branches being visited, see `Event::branches`.
+        branches: Vec<(usize, usize)>,
+        /// The number of `if`s and `match`es seen, to number them.
+        branch_count: usize,
+    }
+
+    impl<'a, 'tcx> Scan<'a, 'tcx> {
+        /// The name of the field the field expression `kind` accesses.
+        fn field_name(&self, kind: &ExprKind<'tcx>) -> Option<Symbol> {
+            let &ExprKind::Field {
+                lhs,
+                variant_index,
+                name,
+            } = kind
+            else {
+                return None;
+            };
+            let ty::Adt(adt, _) = self.thir[lhs].ty.kind() else {
+                return None;
+            };
+            Some(adt.variant(variant_index).fields[name.index()].name)
+        }
+
+        /// The place `expr` refers to, written like in the source.
+        fn path(&self, expr: ExprId) -> Option<String> {
+            let hir = self.tcx.hir();
+            Some(match self.thir[expr].kind {
+                ExprKind::Scope { value, .. } => return self.path(value),
+                ExprKind::VarRef { id } => hir.name(id.0).to_string(),
+                ExprKind::UpvarRef { var_hir_id, .. } => hir.name(var_hir_id.0).to_string(),
+                ExprKind::StaticRef { def_id, .. } => self.tcx.def_path_str(def_id),
+                ExprKind::Deref { arg } => format!("*{}", self.path(arg)?),
+                ExprKind::Field { lhs, .. } => {
+                    let field = self.field_name(&self.thir[expr].kind)?;
+                    format!("{}.{field}", self.path(lhs)?)
+                }
+                ExprKind::Index { lhs, .. } => format!("{}[_]", self.path(lhs)?),
+                _ => return None,
+            })
+        }
+
+        fn push(
+            &mut self,
+            kind: EventKind,
+            union: DefId,
+            field: Option<Symbol>,
+            path: Option<String>,
+            span: Span,
+        ) {
+            self.events.push(Event {
+                kind,
+                union,
+                field,
+                path,
+                span,
+                owner: self.owner,
+                branches: self.branches.clone(),
+            });
+        }
+
+        /// Visit each of `arms` as an arm of a new branch.
+        fn visit_branch(&mut self, arms: impl IntoIterator<Item = impl FnOnce(&mut Self)>) {
+            let branch = self.branch_count;
+            self.branch_count += 1;
+            for (arm, visit) in arms.into_iter().enumerate() {
+                self.branches.push((branch, arm));
+                visit(self);
+                self.branches.pop();
+            }
+        }
+
+        /// If `expr` constructs a union, record that it is written to `path`.
+        fn constructed_into(&mut self, expr: ExprId, path: Option<String>) {
+            let expr = match self.thir[expr].kind {
+                ExprKind::Scope { value, .. } => return self.constructed_into(value, path),
+                _ => &self.thir[expr],
+            };
+            if let ExprKind::Adt(_) = expr.kind
+                && let Some(event) = self
+                    .events
+                    .iter_mut()
+                    .rev()
+                    .find(|event| event.kind == EventKind::Write && event.span == expr.span)
+            {
+                event.path = path;
+            }
+        }
+
+        /// Record that the patterns destructure the union in `scrutinee`.
+        fn destructured(&mut self, scrutinee: ExprId, patterns: impl IntoIterator<Item = Span>) {
+            let Some(union) = union_of(self.thir[scrutinee].ty) else {
+                return;
+            };
+            let path = self.path(scrutinee);
+            for span in patterns {
+                self.push(EventKind::PatternRead, union, None, path.clone(), span);
+            }
+        }
+    }
+
+    impl<'a, 'tcx> Visitor<'a, 'tcx> for Scan<'a, 'tcx> {
+        fn thir(&self) -> &'a Thir<'tcx> {
+            self.thir
+        }
+
+        fn visit_stmt(&mut self, stmt: &Stmt<'tcx>) {
+            visit::walk_stmt(self, stmt);
+            if let StmtKind::Let {
+                pattern,
+                initializer: Some(init),
+                ..
+            } = &stmt.kind
+            {
+                match pattern.kind {
+                    PatKind::Binding { var, .. } => {
+                        let path = self.tcx.hir().name(var.0).to_string();
+                        self.constructed_into(*init, Some(path));
+                    }
+                    _ => self.destructured(*init, [pattern.span]),
+                }
+            }
+        }
+
+        fn visit_expr(&mut self, expr: &Expr<'tcx>) {
+            match &expr.kind {
+                ExprKind::Assign { lhs, rhs } => {
+                    // The right side is evaluated before the assignment.
+                    self.visit_expr(&self.thir[*rhs]);
+                    if let ExprKind::Field { lhs: base, .. } = self.thir[*lhs].kind
+                        && let Some(union) = union_of(self.thir[base].ty)
+                    {
+                        let field = self.field_name(&self.thir[*lhs].kind);
+                        let path = self.path(base);
+                        self.push(EventKind::Write, union, field, path, expr.span);
+                    } else {
+                        self.constructed_into(*rhs, self.path(*lhs));
+                        self.visit_expr(&self.thir[*lhs]);
+                    }
+                    return;
+                }
+                ExprKind::AssignOp { lhs, rhs, .. } => {
+                    // The field is read by the visit of the left side, and then written.
+                    self.visit_expr(&self.thir[*rhs]);
+                    self.visit_expr(&self.thir[*lhs]);
+                    if let ExprKind::Field { lhs: base, .. } = self.thir[*lhs].kind
+                        && let Some(union) = union_of(self.thir[base].ty)
+                    {
+                        let field = self.field_name(&self.thir[*lhs].kind);
+                        let path = self.path(base);
+                        self.push(EventKind::Write, union, field, path, expr.span);
+                    }
+                    return;
+                }
+                ExprKind::If {
+                    cond,
+                    then,
+                    else_opt,
+                    ..
+                } => {
+                    let thir = self.thir;
+                    self.visit_expr(&thir[*cond]);
+                    let arms: [Option<ExprId>; 2] = [Some(*then), *else_opt];
+                    self.visit_branch(arms.into_iter().map(|arm| {
+                        move |scan: &mut Self| {
+                            if let Some(arm) = arm {
+                                scan.visit_expr(&thir[arm]);
+                            }
+                        }
+                    }));
+                    return;
+                }
+                ExprKind::Field { lhs, .. } => {
+                    if let Some(union) = union_of(self.thir[*lhs].ty) {
+                        let field = self.field_name(&expr.kind);
+                        let path = self.path(*lhs);
+                        self.push(EventKind::Read, union, field, path, expr.span);
+                    }
+                }
+                ExprKind::Adt(adt) if adt.adt_def.is_union() => {
+                    let variant = adt.adt_def.variant(adt.variant_index);
+                    for field in adt.fields.iter() {
+                        let name = variant.fields[field.name.index()].name;
+                        let union = adt.adt_def.did();
+                        self.push(EventKind::Write, union, Some(name), None, expr.span);
+                    }
+                }
+                ExprKind::Match { scrutinee, arms } => {
+                    let thir = self.thir;
+                    let patterns = arms.iter().map(|arm| thir[*arm].pattern.span).collect_vec();
+                    self.destructured(*scrutinee, patterns);
+                    self.visit_expr(&thir[*scrutinee]);
+                    self.visit_branch(
+                        arms.iter()
+                            .map(|arm| move |scan: &mut Self| scan.visit_arm(&thir[*arm])),
+                    );
+                    return;
+                }
+                ExprKind::Let { expr: scrutinee, pat } => {
+                    self.destructured(*scrutinee, [pat.span]);
+                }
+                ExprKind::Closure(closure) => {
+                    scan(self.tcx, closure.closure_id, self.owner, self.events);
+                }
+                _ => {}
+            }
+            visit::walk_expr(self, expr);
+        }
+    }
+
+    let Ok((thir, expr)) = tcx.thir_body(unsafety_visitor::with_opt_const_param(tcx, did)) else {
+        return;
+    };
+    let thir = &thir.borrow();
+    if thir.exprs.is_empty() {
+        return;
+    }
+    Scan {
+        tcx,
+        thir,
+        owner,
+        events,
+        branches: vec![],
+        branch_count: 0,
+    }
+    .visit_expr(&thir[expr]);
+}
+
+/// A read of a union found by the unsafety visitor.
+#[derive(Debug)]
+pub struct Read {
+    pub owner: LocalDefId,
+    pub span: Span,
+    /// The field read, `None` when the whole union is matched on.
+    pub field: Option<Symbol>,
+    /// Whether the field is read directly, borrowed or matched on.
+    pub access: UnionAccess,
+    /// A write to the same place that may be the last before the read, if it wrote a different
+    /// field.
+    pub punned: Option<(Symbol, Span)>,
+}
+
+pub struct UnionsCallback;
+
+impl UnionsCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let hir = tcx.hir();
+        let mut reads: HashMap<DefId, Vec<Read>> = HashMap::new();
+        let mut writes: HashMap<DefId, Vec<(Option<Symbol>, LocalDefId, Span)>> = HashMap::new();
+        for id in hir.items() {
+            let item = hir.item(id);
+            if let hir::ItemKind::Union(..) = item.kind {
+                let did = item.def_id.def_id.to_def_id();
+                reads.entry(did).or_default();
+                writes.entry(did).or_default();
+            }
+        }
+
+        for did in hir.body_owners() {
+            if tcx.is_closure(did.to_def_id()) {
+                continue;
+            }
+            let mut events = vec![];
+            scan(tcx, did, did, &mut events);
+            for event in &events {
+                if event.kind == EventKind::Write {
+                    writes.entry(event.union).or_default().push((
+                        event.field,
+                        event.owner,
+                        event.span,
+                    ));
+                }
+            }
+
+            let violations = unsafety_visitor::check_unsafety(
+                tcx,
+                unsafety_visitor::with_opt_const_param(tcx, did),
+            );
+            for (kind, owner, span) in violations {
+                let UnsafeOpKind::AccessToUnionField(union, field, access) = kind else {
+                    continue;
+                };
+                // The visitor reports the field expression, or the binding in the pattern.
+                let position = events.iter().position(|event| match event.kind {
+                    EventKind::Read => event.span == span,
+                    EventKind::PatternRead => event.span.contains(span),
+                    EventKind::Write => false,
+                });
+                let punned = position.and_then(|position| {
+                    last_writes(&events, position).into_iter().find_map(|write| {
+                        let written = write.field?;
+                        (field.is_some() && field != Some(written)).then_some((written, write.span))
+                    })
+                });
+                reads.entry(union).or_default().push(Read {
+                    owner,
+                    span,
+                    field,
+                    access,
+                    punned,
+                });
+            }
+        }
+
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut files = Files::new(tcx.sess.source_map());
+        let (mut read_count, mut write_count, mut punned_count) = (0, 0, 0);
+        // External unions that are only written show up in `writes` alone.
+        let unions = reads
+            .keys()
+            .chain(writes.keys())
+            .copied()
+            .unique()
+            .sorted_by_cached_key(|union| tcx.def_path_str(*union))
+            .collect_vec();
+        let union_count = unions.len();
+        for union in unions {
+            let reads = reads.remove(&union).unwrap_or_default();
+            let writes = writes.remove(&union).unwrap_or_default();
+            read_count += reads.len();
+            write_count += writes.len();
+            let field = |field: Option<Symbol>| match field {
+                Some(field) => format!("`{}::{field}`", tcx.item_name(union)),
+                None => format!("`{}`", tcx.item_name(union)),
+            };
+
+            let mut labels = vec![];
+            if union.is_local() {
+                labels.extend(files.label(tcx.def_span(union), LabelStyle::Primary, ""));
+            }
+            for (written, owner, span) in &writes {
+                labels.extend(files.label(
+                    *span,
+                    LabelStyle::Secondary,
+                    format!(
+                        "write of {} in `{}`",
+                        field(*written),
+                        tcx.def_path_str(owner.to_def_id())
+                    ),
+                ));
+            }
+            for read in &reads {
+                let in_owner = tcx.def_path_str(read.owner.to_def_id());
+                match read.punned {
+                    Some((written, _)) => {
+                        punned_count += 1;
+                        labels.extend(files.label(
+                            read.span,
+                            LabelStyle::Primary,
+                            format!(
+                                "{} of {} in `{in_owner}`, but {} may have been written last",
+                                read.access.description(),
+                                field(read.field),
+                                field(Some(written))
+                            ),
+                        ));
+                    }
+                    None => labels.extend(files.label(
+                        read.span,
+                        LabelStyle::Secondary,
+                        format!(
+                            "{} of {} in `{in_owner}`",
+                            read.access.description(),
+                            field(read.field)
+                        ),
+                    )),
+                }
+            }
+
+            let fields = tcx
+                .adt_def(union)
+                .all_fields()
+                .map(|def| {
+                    let reads = reads.iter().filter(|read| read.field == Some(def.name));
+                    let writes = writes.iter().filter(|(field, ..)| *field == Some(def.name));
+                    format!(
+                        "`{}`: {} reads, {} writes",
+                        def.name,
+                        reads.count(),
+                        writes.count()
+                    )
+                })
+                .join("\n");
+            let mut notes = vec![fields];
+            if reads.iter().any(|read| read.punned.is_some()) {
+                notes.push(
+                    "reading a different field than the one last written reinterprets its bytes, \
+                     check that every bit pattern of the written field is valid for the read one"
+                        .to_string(),
+                );
+            }
+            files.emit(
+                &mut io,
+                &Diagnostic::note()
+                    .with_message(format!("union `{}`", tcx.def_path_str(union)))
+                    .with_labels(labels)
+                    .with_notes(notes),
+            )?;
+        }
+        writeln!(
+            io,
+            "{union_count} unions, {read_count} reads and {write_count} writes, {punned_count} \
+             reads of a different field than the one last written"
+        )?;
+        Ok(())
+    }
+}
+
+impl rustc_driver::Callbacks for UnionsCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    UnionsCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
+
+#[test]
+#[cfg(test)]
+fn test_last_writes() {
+    let event = |kind, branches: &[(usize, usize)]| Event {
+        kind,
+        union: rustc_span::def_id::CRATE_DEF_ID.to_def_id(),
+        field: None,
+        path: Some("bits".to_string()),
+        span: rustc_span::DUMMY_SP,
+        owner: rustc_span::def_id::CRATE_DEF_ID,
+        branches: branches.to_vec(),
+    };
+    let events = [
+        event(EventKind::Write, &[]),
+        event(EventKind::Write, &[]),
+        event(EventKind::Write, &[(0, 0)]),
+        event(EventKind::Write, &[(0, 1), (1, 0)]),
+        event(EventKind::Read, &[(0, 0)]),
+        event(EventKind::Read, &[]),
+    ];
+    let branches = |read| {
+        last_writes(&events, read)
+            .into_iter()
+            .map(|write| write.branches.clone())
+            .collect_vec()
+    };
+    // In the arm, the write in the other arm can't have happened.
+    assert_eq!(branches(4), vec![vec![(0, 0)]]);
+    // After the branch, both arms and the write before them may have been last.
+    assert_eq!(branches(5), vec![vec![], vec![(0, 0)], vec![(0, 1), (1, 0)]]);
+}
@@ -0,0 +1,149 @@
+//! Simulate making an unsafe fn safe, and report the unsafety that would go away with it.
+use std::{ffi::OsString, io::Write};
+
+use codespan_reporting::diagnostic::{Diagnostic, LabelStyle};
+use eyre::{Context, Result};
+use rustc_hir as hir;
+use rustc_middle::ty::TyCtxt;
+
+use crate::{
+    report::Files,
+    run::cargo_check,
+    safe::{
+        unsafety_visitor::{self, UnsafeOpKind},
+        FakeCallback,
+    },
+};
+
+pub(crate) fn run(args: crate::opts::WhatIfArgs, rem: &[String]) -> Result<()> {
+    std::env::set_var(crate::ENV_VAR_WHYNOT_COLORING, args.color.to_string());
+    tracing::debug!("simulating making {} safe", args.make_safe);
+    cargo_check(
+        "what-if",
+        Some(args.make_safe.to_string()),
+        &args.package,
+        Some("-Zthir-unsafeck"),
+        rem,
+    )
+}
+
+pub(crate) fn run_rustc(rem: &[OsString]) -> Result<()> {
+    assert_eq!(
+        std::env::var(crate::ENV_VAR_WHYNOT_MODE).as_deref(),
+        Ok("what-if")
+    );
+    tracing::trace!("in whynot what-if rustc with rem: `{rem:?}`");
+
+    crate::run::rustc_run(Some(&mut WhatIfCallback), None, &rem[1..])?;
+
+    Ok(())
+}
+
+pub struct WhatIfCallback;
+
+impl WhatIfCallback {
+    pub fn run(&self, tcx: TyCtxt<'_>) -> Result<()> {
+        let hir = tcx.hir();
+        let selector = std::env::var(crate::ENV_VAR_WHYNOT_SELECTOR)
+            .wrap_err(crate::WHYNOT_RUSTC_WRAPPER_ERROR)?;
+        let (target, header) = FakeCallback { selector }.search(tcx)?;
+        let target_path = tcx.def_path_str(target.to_def_id());
+        eyre::ensure!(
+            header.unsafety == hir::Unsafety::Unsafe,
+            "`{target_path}` is already safe"
+        );
+        let call = UnsafeOpKind::CallToUnsafeFunction(Some(target.to_def_id()));
+        let is_target = |kind: &UnsafeOpKind| *kind == call;
+
+        let color = crate::coloring_from_env()?;
+        let mut io = termcolor::StandardStream::stdout(color.into());
+        let mut files = Files::new(tcx.sess.source_map());
+        let (mut calls, mut blocks, mut fns) = (0, 0, 0);
+
+        for did in hir.body_owners() {
+            if tcx.is_closure(did.to_def_id()) || did == target {
+                continue;
+            }
+            let report = unsafety_visitor::check_body_unsafety(
+                tcx,
+                unsafety_visitor::with_opt_const_param(tcx, did),
+            );
+            calls += report
+                .violations
+                .iter()
+                .filter(|(kind, _, _)| is_target(kind))
+                .count();
+
+            for block in &report.unsafe_blocks {
+                if block.ops.is_empty() || !block.ops.iter().all(|(kind, _)| is_target(kind)) {
+                    continue;
+                }
+                blocks += 1;
+                let mut labels = vec![];
+                labels.extend(files.label(block.span, LabelStyle::Primary, "unsafe block"));
+                for (_, span) in &block.ops {
+                    labels.extend(files.label(
+                        *span,
+                        LabelStyle::Secondary,
+                        format!("call to `{target_path}`"),
+                    ));
+                }
+                files.emit(
+                    &mut io,
+                    &Diagnostic::note()
+                        .with_message("unsafe block would become unnecessary")
+                        .with_labels(labels),
+                )?;
+            }
+
+            let is_unsafe_fn = unsafety_visitor::is_unsafe_fn(tcx, did.to_def_id());
+            if is_unsafe_fn
+                && !report.violations.is_empty()
+                && report.violations.iter().all(|(kind, _, _)| is_target(kind))
+            {
+                fns += 1;
+                let labels = files
+                    .label(tcx.def_span(did), LabelStyle::Primary, "")
+                    .into_iter()
+                    .collect();
+                files.emit(
+                    &mut io,
+                    &Diagnostic::note()
+                        .with_message(format!(
+                            "unsafe fn `{}` would only be unsafe by choice",
+                            tcx.def_path_str(did.to_def_id())
+                        ))
+                        .with_labels(labels),
+                )?;
+            }
+        }
+
+        writeln!(
+            io,
+            "if `{target_path}` were safe, {calls} calls would no longer need `unsafe`, \
+             {blocks} unsafe blocks would become unnecessary and {fns} unsafe fns would only be \
+             unsafe by choice"
+        )?;
+        Ok(())
+    }
+}
+
+impl rustc_driver::Callbacks for WhatIfCallback {
+    fn config(&mut self, config: &mut rustc_interface::interface::Config) {
+        config.override_queries = Some(|_, p, _| p.thir_check_unsafety = check_unsafety);
+    }
+}
+
+pub fn check_unsafety<'tcx>(
+    tcx: TyCtxt<'tcx>,
+    _: rustc_middle::ty::query::query_keys::thir_check_unsafety<'tcx>,
+) {
+    WhatIfCallback
+        .run(tcx)
+        .map_err(|e| {
+            let _hook = std::panic::take_hook();
+            tcx.sess.fatal(e.to_string());
+        })
+        .unwrap();
+    std::process::exit(1);
+}
@@ -1,25 +1,53 @@
 #![feature(rustc_private)]
 #![feature(let_chains, box_patterns)]
 
+extern crate rustc_ast;
 extern crate rustc_codegen_ssa;
 extern crate rustc_driver;
 extern crate rustc_errors;
 extern crate rustc_hir;
+extern crate rustc_infer;
 extern crate rustc_interface;
 extern crate rustc_metadata;
 extern crate rustc_middle;
 extern crate rustc_session;
 extern crate rustc_span;
+extern crate rustc_trait_selection;
 
 pub static ENV_VAR_WHYNOT_MODE: &str = "__CARGO-WHYNOT_MODE";
 pub static ENV_VAR_WHYNOT_COLORING: &str = "__CARGO-WHYNOT_COLORING";
 pub static ENV_VAR_WHYNOT_SELECTOR: &str = "__CARGO-WHYNOT_SELECTOR";
+pub static ENV_VAR_WHYNOT_CONFIG: &str = "__CARGO-WHYNOT_CONFIG";
+pub static ENV_VAR_WHYNOT_FAILURE: &str = "__CARGO-WHYNOT_FAILURE";
+pub static ENV_VAR_WHYNOT_METRICS: &str = "__CARGO-WHYNOT_METRICS";
+pub static ENV_VAR_WHYNOT_BASELINE: &str = "__CARGO-WHYNOT_BASELINE";
+pub static ENV_VAR_WHYNOT_SAVE_BASELINE: &str = "__CARGO-WHYNOT_SAVE_BASELINE";
+pub static ENV_VAR_WHYNOT_CHANGED_SINCE: &str = "__CARGO-WHYNOT_CHANGED_SINCE";
+pub static ENV_VAR_WHYNOT_LEDGER: &str = "__CARGO-WHYNOT_LEDGER";
+pub static ENV_VAR_WHYNOT_LEDGER_PARTS: &str = "__CARGO-WHYNOT_LEDGER_PARTS";
+pub static ENV_VAR_WHYNOT_REVIEWER: &str = "__CARGO-WHYNOT_REVIEWER";
+pub static ENV_VAR_WHYNOT_REVIEWERS: &str = "__CARGO-WHYNOT_REVIEWERS";
+pub static ENV_VAR_WHYNOT_CANDIDATES: &str = "__CARGO-WHYNOT_CANDIDATES";
+pub static ENV_VAR_WHYNOT_ALL_CALLERS: &str = "__CARGO-WHYNOT_ALL_CALLERS";
+pub static ENV_VAR_WHYNOT_TRAIT: &str = "__CARGO-WHYNOT_TRAIT";
 pub static WHYNOT_RUSTC_WRAPPER_ERROR: &str = "ran `cargo whynot rustc` outside of wrapper";
 
+mod asm;
+mod audit;
+mod callers;
+mod check;
+mod config;
+mod impls;
+mod ledger;
 mod opts;
+mod report;
 mod run;
 mod safe;
+mod safety_comments;
+mod statics;
+mod unions;
 mod utils;
+mod what_if;
 use std::str::FromStr;
 
 use clap::Parser;
@@ -35,10 +63,30 @@ fn main() -> eyre::Result<()> {
             utils::install_utils()?;
             match sc {
                 SubCommand::Safe(args) => safe::run(args, &[])?,
+                SubCommand::Audit(args) => audit::run(args, &[])?,
+                SubCommand::Check(args) => check::run(args, &[])?,
+                SubCommand::Ledger(args) => ledger::run(args, &[])?,
+                SubCommand::SafetyComments(args) => safety_comments::run(args, &[])?,
+                SubCommand::WhatIf(args) => what_if::run(args, &[])?,
+                SubCommand::Callers(args) => callers::run(args, &[])?,
+                SubCommand::Impl(args) => impls::run(args, &[])?,
+                SubCommand::Statics(args) => statics::run(args, &[])?,
+                SubCommand::Unions(args) => unions::run(args, &[])?,
+                SubCommand::Asm(args) => asm::run(args, &[])?,
             }
         }
         Opts::Rustc(external) => match std::env::var(ENV_VAR_WHYNOT_MODE).as_deref() {
             Ok("safe") => safe::run_rustc(&external)?,
+            Ok("audit") => audit::run_rustc(&external)?,
+            Ok("check") => check::run_rustc(&external)?,
+            Ok("ledger") => ledger::run_rustc(&external)?,
+            Ok("safety-comments") => safety_comments::run_rustc(&external)?,
+            Ok("what-if") => what_if::run_rustc(&external)?,
+            Ok("callers") => callers::run_rustc(&external)?,
+            Ok("impl") => impls::run_rustc(&external)?,
+            Ok("statics") => statics::run_rustc(&external)?,
+            Ok("unions") => unions::run_rustc(&external)?,
+            Ok("asm") => asm::run_rustc(&external)?,
             _ => eyre::bail!(WHYNOT_RUSTC_WRAPPER_ERROR),
         },
     }
@@ -55,3 +103,12 @@ pub fn parse_selector(s: &str) -> Result<Selector, <Selector as FromStr>::Err> {
         s.parse()
     }
 }
+
+/// Get the coloring passed on from `cargo whynot` to the rustc wrapper.
+pub fn coloring_from_env() -> eyre::Result<opts::Coloring> {
+    use eyre::WrapErr;
+    std::env::var(ENV_VAR_WHYNOT_COLORING)
+        .wrap_err(WHYNOT_RUSTC_WRAPPER_ERROR)?
+        .parse()
+        .wrap_err(WHYNOT_RUSTC_WRAPPER_ERROR)
+}
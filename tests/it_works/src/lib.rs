@@ -39,3 +39,104 @@ pub mod unsafe_mod {
     }
     pub unsafe fn unsafety_not_really() {}
 }
+
+pub struct RawBuf(*mut u8);
+
+unsafe impl Send for RawBuf {}
+
+pub unsafe trait Zeroable {}
+
+unsafe impl Zeroable for u32 {}
+
+pub mod statics {
+    pub static mut COUNTER: u32 = 0;
+    pub static mut UNTOUCHED: u32 = 0;
+
+    fn worker() {
+        unsafe { COUNTER += 1 };
+    }
+
+    /// Not a thread, despite its name.
+    fn spawn<F: FnOnce()>(f: F) {
+        f()
+    }
+
+    pub fn run() {
+        let handle = std::thread::spawn(|| unsafe { COUNTER += 1 });
+        std::thread::spawn(worker).join().unwrap();
+        handle.join().unwrap();
+        std::thread::scope(|s| {
+            s.spawn(|| unsafe { COUNTER });
+        });
+        spawn(|| unsafe { UNTOUCHED += 1 });
+    }
+}
+
+pub mod unions {
+    #[repr(C)]
+    pub union Bits {
+        pub float: f32,
+        pub int: u32,
+    }
+
+    pub fn punned(float: f32) -> u32 {
+        let bits = Bits { float };
+        unsafe { bits.int }
+    }
+
+    pub fn branch(flag: bool) -> u32 {
+        let mut bits = Bits { int: 0 };
+        if flag {
+            bits.float = 1.0;
+        } else {
+            bits.int = 1;
+        }
+        unsafe { bits.int }
+    }
+
+    pub fn compound(float: f32) -> u32 {
+        let mut bits = Bits { float };
+        unsafe {
+            bits.int += 1;
+            bits.int
+        }
+    }
+
+    pub fn destructure(bits: Bits) -> u32 {
+        unsafe {
+            match bits {
+                Bits { int: 0 } => 0,
+                Bits { int } => int,
+            }
+        }
+    }
+
+    pub fn borrowed(bits: &mut Bits) -> &mut f32 {
+        unsafe { &mut bits.float }
+    }
+}
+
+#[cfg(all(
+    target_arch = "x86_64",
+    target_feature = "sse2"
+))]
+pub mod asm {
+    use std::arch::asm;
+
+    pub fn load(value: &u64) -> u64 {
+        let loaded;
+        unsafe {
+            asm!(
+                "mov {}, [{}]",
+                out(reg) loaded,
+                in(reg) value,
+                options(readonly, nostack, preserves_flags)
+            );
+        }
+        loaded
+    }
+
+    pub fn clobbering() {
+        unsafe { asm!("nop", clobber_abi("C")) }
+    }
+}